@@ -1,68 +1,118 @@
 (async () => {
   const {
     default: init,
-    start_websocket,
-    send_location,
+    start_websocket_with_renderer,
+    stop_websocket,
+    send_pointer,
+    send_ping,
+    send_selection,
+    set_element_anchoring,
+    set_block_list,
+    set_block_list_callback,
+    set_site_settings,
+    site_blocked_reason,
+    set_page_policy,
+    policy_meta_name,
+    policy_well_known_path,
   } = await import(chrome.runtime.getURL("pkg/client.js"));
   //init, { start_websocket, send_location } =
-  init().then(() => {
-    let id_map = {};
+  init().then(async () => {
+    const host = window.location.hostname;
+    let r = null;
 
-    let move_cursor_cb = function (id, x, y) {
-      if (id in id_map) {
-        let element = id_map[id];
-
-        let pixel_x = window.innerWidth * x;
-        let pixel_y = window.innerHeight * y;
-        element.style.top = `${pixel_y}px`;
-        element.style.left = `${pixel_x}px`;
-      }
+    // what the site's owner declared is only fetched once the user's own
+    // settings let us run here, see PagePolicy
+    let pagePolicy = null;
+    const loadPagePolicy = () => {
+      pagePolicy ??= (async () => {
+        const meta = document.querySelector(
+          `meta[name="${policy_meta_name()}"]`
+        );
+        const wellKnown = await fetch(policy_well_known_path(), {
+          credentials: "omit",
+        })
+          .then((response) => (response.ok ? response.text() : null))
+          .catch(() => null);
+        try {
+          set_page_policy(meta?.content ?? null, wellKnown);
+          return true;
+        } catch (e) {
+          console.log(e);
+          return false;
+        }
+      })();
+      return pagePolicy;
     };
 
-    let spawn_cursor_cb = function (id, icon) {
-      console.log(`spawning ${id}`);
-      let element = document.createElement("div");
-      element.className = "server-cursor";
-      id_map[id] = element;
+    const start = async () => {
+      const reason = site_blocked_reason(host);
+      if (reason) {
+        console.log(`multiplayer is off on ${host} (${reason})`);
+        return;
+      }
+      // settings may change while the policy loads
+      if (!(await loadPagePolicy()) || r || site_blocked_reason(host)) {
+        return;
+      }
       try {
-        element.style.top = `0px`;
-        element.style.left = `0px`;
-        element.style.position = `fixed`;
-        element.style.background = `red`;
-        element.style.width = `10px`;
-        element.style.height = `10px`;
-        element.style.zIndex = `99999`;
-
-        document.getElementsByTagName("html")[0].append(element);
+        // cursors are drawn by the wasm client, use start_websocket to draw
+        // them from custom callbacks instead
+        r = start_websocket_with_renderer(host);
       } catch (e) {
         console.log(e);
+        return;
       }
-    };
+      set_element_anchoring(r, true);
 
-    let despawn_cursor_cb = function (id) {
-      if (id in id_map) {
-        console.log(`despawning ${id}`);
-        let element = id_map[id];
-        element.remove();
-        delete id_map[id];
-      }
+      // hiding everyone is remembered on this site, single peers aren't:
+      // their ids change with every visit
+      const hideAllKey = `hideAll:${host}`;
+      const socket = r;
+      chrome.storage.local.get(hideAllKey).then((saved) => {
+        if (saved[hideAllKey]) {
+          set_block_list(socket, { users: [], all: true });
+        }
+        set_block_list_callback(socket, (list) =>
+          chrome.storage.local.set({ [hideAllKey]: list.all })
+        );
+      });
     };
 
-    let r = start_websocket(
-      move_cursor_cb,
-      spawn_cursor_cb,
-      despawn_cursor_cb,
-      window.location.hostname
-    );
+    // where multiplayer runs is set from the popup
+    const { sitePolicy } = await chrome.storage.sync.get("sitePolicy");
+    if (sitePolicy) {
+      set_site_settings(sitePolicy);
+    }
+    start();
 
-    console.log(r);
+    chrome.storage.onChanged.addListener((changes, area) => {
+      if (area !== "sync" || !changes.sitePolicy?.newValue) {
+        return;
+      }
+      set_site_settings(changes.sitePolicy.newValue);
+      if (r && site_blocked_reason(host)) {
+        stop_websocket(r);
+        r = null;
+      } else if (!r) {
+        start();
+      }
+    });
 
     let hmtl = document.getElementsByTagName("html")[0];
 
     hmtl.onmousemove = (ev) => {
-      let percent_x = ev.pageX / window.innerWidth;
-      let percent_y = ev.pageY / window.innerHeight;
-      send_location(r, percent_x, percent_y);
+      if (r) {
+        send_pointer(r, ev.clientX, ev.clientY);
+      }
     };
+
+    document.addEventListener("selectionchange", () => r && send_selection(r));
+
+    // alt+click pings the spot for everyone on the page
+    hmtl.addEventListener("click", (ev) => {
+      if (r && ev.altKey) {
+        send_ping(r, ev.clientX, ev.clientY, ev.shiftKey ? "look" : "ripple");
+      }
+    });
   });
 })();
@@ -0,0 +1,75 @@
+import init, {
+  pause_site,
+  set_site_settings,
+  site_blocked_reason,
+  site_categories,
+  site_settings,
+} from "./pkg/client.js";
+
+const lines = (text) =>
+  text
+    .split("\n")
+    .map((line) => line.trim())
+    .filter((line) => line);
+
+init().then(async () => {
+  const [tab] = await chrome.tabs.query({ active: true, currentWindow: true });
+  const host = tab?.url ? new URL(tab.url).hostname : "";
+
+  const { sitePolicy } = await chrome.storage.sync.get("sitePolicy");
+  if (sitePolicy) {
+    set_site_settings(sitePolicy);
+  }
+
+  const paused = document.getElementById("paused");
+  const allow = document.getElementById("allow");
+  const deny = document.getElementById("deny");
+  const categories = document.getElementById("categories");
+
+  // open tabs pick the change up from storage
+  const save = (settings) => {
+    set_site_settings(settings);
+    chrome.storage.sync.set({ sitePolicy: settings });
+    render();
+  };
+
+  const render = () => {
+    const settings = site_settings();
+    const reason = site_blocked_reason(host);
+    document.getElementById("host").textContent = host;
+    document.getElementById("status").textContent = reason
+      ? `Multiplayer is off here (${reason}).`
+      : "Multiplayer is on here.";
+    paused.checked = reason === "paused";
+    allow.value = settings.allow.join("\n");
+    deny.value = settings.deny.join("\n");
+    for (const box of categories.querySelectorAll("input")) {
+      box.checked = settings.allowedCategories.includes(box.value);
+    }
+  };
+
+  for (const name of site_categories()) {
+    const label = document.createElement("label");
+    const box = document.createElement("input");
+    box.type = "checkbox";
+    box.value = name;
+    box.onchange = () => {
+      const settings = site_settings();
+      settings.allowedCategories = settings.allowedCategories.filter(
+        (category) => category !== name
+      );
+      if (box.checked) {
+        settings.allowedCategories.push(name);
+      }
+      save(settings);
+    };
+    label.append(box, ` ${name} sites`);
+    categories.append(label);
+  }
+
+  paused.onchange = () => save(pause_site(host, paused.checked));
+  allow.onchange = () => save({ ...site_settings(), allow: lines(allow.value) });
+  deny.onchange = () => save({ ...site_settings(), deny: lines(deny.value) });
+
+  render();
+});
@@ -0,0 +1,102 @@
+use crate::viewport::PageMetrics;
+use shared::{ElementAnchor, MAX_ANCHOR_PATH};
+use web_sys::Element;
+
+/// Describes the point under `client_x`/`client_y` (viewport pixels) relative
+/// to the element found there.
+pub fn anchor_at(client_x: f64, client_y: f64) -> Option<ElementAnchor> {
+    let document = web_sys::window()?.document()?;
+    let element = document.element_from_point(client_x as f32, client_y as f32)?;
+
+    // the page itself is covered well enough by document coordinates
+    if Some(&element) == document.document_element().as_ref()
+        || Some(&element) == document.body().map(Element::from).as_ref()
+    {
+        return None;
+    }
+
+    let rect = element.get_bounding_client_rect();
+    if rect.width() <= 0.0 || rect.height() <= 0.0 {
+        return None;
+    }
+
+    let path = element_path(&element)?;
+    Some(ElementAnchor {
+        path,
+        x: ((client_x - rect.left()) / rect.width()) as f32,
+        y: ((client_y - rect.top()) / rect.height()) as f32,
+    })
+}
+
+/// Finds the anchor's element in this page and returns the position in document space.
+pub fn resolve(anchor: &ElementAnchor, metrics: &PageMetrics) -> Option<(f32, f32)> {
+    let document = web_sys::window()?.document()?;
+    let element = document.query_selector(&anchor.path).ok()??;
+    let rect = element.get_bounding_client_rect();
+    let client_x = rect.left() + rect.width() * anchor.x as f64;
+    let client_y = rect.top() + rect.height() * anchor.y as f64;
+    Some(metrics.document_position(client_x, client_y))
+}
+
+/// Builds a selector from the closest ancestor with a usable id (or the
+/// document root) down to `element`, using `nth-of-type` for each step.
+pub(crate) fn element_path(element: &Element) -> Option<String> {
+    let mut segments = Vec::new();
+    let mut length = 0;
+    let mut current = Some(element.clone());
+
+    while let Some(element) = current {
+        let id = element.id();
+        let segment = if is_plain_identifier(&id) {
+            format!("#{}", id)
+        } else {
+            let tag = element.tag_name().to_lowercase();
+            if tag == "html" {
+                tag
+            } else {
+                format!("{}:nth-of-type({})", tag, type_index(&element))
+            }
+        };
+
+        length += segment.len() + 3;
+        if length > MAX_ANCHOR_PATH {
+            return None;
+        }
+
+        let rooted = segment.starts_with('#') || segment == "html";
+        segments.push(segment);
+        if rooted {
+            segments.reverse();
+            return Some(segments.join(" > "));
+        }
+        current = element.parent_element();
+    }
+
+    // detached or inside a shadow tree, not addressable from the document
+    None
+}
+
+/// 1-based position of `element` among its siblings with the same tag.
+fn type_index(element: &Element) -> u32 {
+    let tag = element.tag_name();
+    let mut index = 1;
+    let mut sibling = element.previous_element_sibling();
+    while let Some(s) = sibling {
+        if s.tag_name() == tag {
+            index += 1;
+        }
+        sibling = s.previous_element_sibling();
+    }
+    index
+}
+
+/// Ids that can be used in a selector without escaping.
+fn is_plain_identifier(id: &str) -> bool {
+    let mut chars = id.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        }
+        _ => false,
+    }
+}
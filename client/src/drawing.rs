@@ -0,0 +1,269 @@
+use crate::viewport::PageMetrics;
+use shared::{
+    CoordinateSpace, Stroke, StrokeAppend, StrokeEnd, StrokeErase, StrokePoint, StrokeStart,
+    MAX_STROKE_POINTS,
+};
+use std::collections::HashSet;
+
+/// A stroke ready to draw: `0xRRGGBB` color, width in CSS pixels and points
+/// as fractions of the viewport.
+pub struct StrokePath {
+    pub color: u32,
+    pub width: f32,
+    pub points: Vec<(f32, f32)>,
+}
+
+/// The strokes drawn over the page, ours included, in drawing order.
+pub struct Sketch {
+    /// Our own user id.
+    author: u64,
+    strokes: Vec<Stroke>,
+    /// Our stroke being drawn and its points not sent yet.
+    drawing: Option<(u32, Vec<StrokePoint>)>,
+    /// `(author, stroke)` of strokes whose author lifted the pen, no points
+    /// are added to them anymore.
+    finished: HashSet<(u64, u32)>,
+    next_stroke: u32,
+    /// Set when strokes changed since they were last drawn.
+    pub dirty: bool,
+}
+
+impl Sketch {
+    pub fn new(author: u64) -> Sketch {
+        Sketch {
+            author,
+            strokes: Vec::new(),
+            drawing: None,
+            finished: HashSet::new(),
+            next_stroke: 1,
+            dirty: false,
+        }
+    }
+
+    /// Begins a stroke of ours. `end` the previous one first.
+    pub fn start(&mut self, color: u32, width: f32) -> StrokeStart {
+        let start = StrokeStart {
+            id: self.author,
+            stroke: self.next_stroke,
+            color,
+            width,
+        };
+        self.next_stroke = self.next_stroke.wrapping_add(1);
+        self.apply_start(&start);
+        self.drawing = Some((start.stroke, Vec::new()));
+        start
+    }
+
+    /// Adds a point to the stroke we are drawing.
+    pub fn extend(&mut self, point: StrokePoint) {
+        let Some((stroke, pending)) = &mut self.drawing else {
+            return;
+        };
+        let Some(index) = self
+            .strokes
+            .iter()
+            .position(|s| s.id == self.author && s.stroke == *stroke)
+        else {
+            return;
+        };
+        if self.strokes[index].points.len() >= MAX_STROKE_POINTS
+            || self.strokes[index].points.last() == Some(&point)
+        {
+            return;
+        }
+        self.strokes[index].points.push(point);
+        pending.push(point);
+        self.dirty = true;
+    }
+
+    /// Points of our stroke that haven't been sent yet.
+    pub fn take_pending(&mut self) -> Option<StrokeAppend> {
+        let (stroke, pending) = self.drawing.as_mut()?;
+        if pending.is_empty() {
+            return None;
+        }
+        Some(StrokeAppend {
+            id: self.author,
+            stroke: *stroke,
+            points: std::mem::take(pending),
+        })
+    }
+
+    /// Finishes our stroke. Take the pending points first.
+    pub fn end(&mut self) -> Option<StrokeEnd> {
+        let (stroke, _) = self.drawing.take()?;
+        Some(StrokeEnd {
+            id: self.author,
+            stroke,
+        })
+    }
+
+    pub fn apply_start(&mut self, start: &StrokeStart) {
+        if self.position(start.id, start.stroke).is_some() {
+            return;
+        }
+        self.strokes.push(Stroke {
+            id: start.id,
+            stroke: start.stroke,
+            color: start.color,
+            width: start.width,
+            points: Vec::new(),
+        });
+        self.dirty = true;
+    }
+
+    pub fn apply_append(&mut self, append: &StrokeAppend) {
+        if self.finished.contains(&(append.id, append.stroke)) {
+            return;
+        }
+        if let Some(index) = self.position(append.id, append.stroke) {
+            self.strokes[index].points.extend_from_slice(&append.points);
+            self.dirty = true;
+        }
+    }
+
+    /// The author lifted the pen, later points for the stroke are dropped.
+    pub fn apply_end(&mut self, end: &StrokeEnd) {
+        if self.position(end.id, end.stroke).is_some() {
+            self.finished.insert((end.id, end.stroke));
+        }
+    }
+
+    /// Finishes every stroke of `author`, who left without lifting the pen,
+    /// like the server does.
+    pub fn end_all(&mut self, author: u64) {
+        for stroke in self.strokes.iter().filter(|stroke| stroke.id == author) {
+            self.finished.insert((stroke.id, stroke.stroke));
+        }
+    }
+
+    /// Removes the author's latest stroke or all of theirs, like the server does.
+    pub fn apply_erase(&mut self, erase: &StrokeErase) {
+        if erase.all {
+            self.strokes.retain(|stroke| stroke.id != erase.id);
+        } else if let Some(index) = self.strokes.iter().rposition(|s| s.id == erase.id) {
+            self.strokes.remove(index);
+        }
+        let strokes = &self.strokes;
+        self.finished.retain(|(author, stroke)| {
+            strokes
+                .iter()
+                .any(|s| s.id == *author && s.stroke == *stroke)
+        });
+        // stop drawing if our stroke just went away
+        if let Some((stroke, _)) = &self.drawing {
+            if self.position(self.author, *stroke).is_none() {
+                self.drawing = None;
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Replaces all strokes with the room's, as sent after joining.
+    pub fn replace(&mut self, strokes: Vec<Stroke>) {
+        self.strokes = strokes;
+        self.finished.clear();
+        self.drawing = None;
+        self.dirty = true;
+    }
+
+    /// Every stroke with its points in this viewport.
+    pub fn paths(&self, metrics: &PageMetrics) -> Vec<StrokePath> {
+        self.strokes
+            .iter()
+            .filter(|stroke| !stroke.points.is_empty())
+            .map(|stroke| StrokePath {
+                color: stroke.color,
+                width: stroke.width,
+                points: stroke
+                    .points
+                    .iter()
+                    .map(|point| {
+                        let (x, y) = point.position();
+                        metrics.viewport_position(x, y, CoordinateSpace::Document)
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn position(&self, author: u64, stroke: u32) -> Option<usize> {
+        self.strokes
+            .iter()
+            .position(|s| s.id == author && s.stroke == stroke)
+    }
+}
+
+/// Parses a CSS `#rrggbb` color.
+pub fn parse_color(color: &str) -> Option<u32> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_points_are_batched() {
+        let mut sketch = Sketch::new(1);
+        let start = sketch.start(0xff0000, 2.0);
+        sketch.extend(StrokePoint::new(0.1, 0.1));
+        sketch.extend(StrokePoint::new(0.1, 0.1));
+        sketch.extend(StrokePoint::new(0.2, 0.2));
+
+        let append = sketch.take_pending().unwrap();
+        assert_eq!(append.stroke, start.stroke);
+        assert_eq!(append.points.len(), 2);
+        assert!(sketch.take_pending().is_none());
+
+        assert_eq!(sketch.end().unwrap().stroke, start.stroke);
+        sketch.extend(StrokePoint::new(0.3, 0.3));
+        assert!(sketch.take_pending().is_none());
+    }
+
+    #[test]
+    fn ended_strokes_take_no_more_points() {
+        let mut sketch = Sketch::new(3);
+        sketch.apply_start(&StrokeStart {
+            id: 1,
+            stroke: 1,
+            color: 0,
+            width: 1.0,
+        });
+        let append = StrokeAppend {
+            id: 1,
+            stroke: 1,
+            points: vec![StrokePoint::new(0.1, 0.1)],
+        };
+        sketch.apply_append(&append);
+        sketch.apply_end(&StrokeEnd { id: 1, stroke: 1 });
+        sketch.apply_append(&append);
+        assert_eq!(sketch.strokes[0].points.len(), 1);
+    }
+
+    #[test]
+    fn undo_removes_the_authors_latest_stroke() {
+        let mut sketch = Sketch::new(3);
+        for (id, stroke) in [(1, 1), (2, 1), (1, 2)] {
+            sketch.apply_start(&StrokeStart {
+                id,
+                stroke,
+                color: 0,
+                width: 1.0,
+            });
+        }
+
+        sketch.apply_erase(&StrokeErase { id: 1, all: false });
+        assert_eq!(sketch.strokes.len(), 2);
+        assert_eq!(sketch.strokes[0].stroke, 1);
+
+        sketch.apply_erase(&StrokeErase { id: 2, all: true });
+        assert_eq!(sketch.strokes.len(), 1);
+        assert_eq!(parse_color("#00ff80"), Some(0x00ff80));
+        assert_eq!(parse_color("red"), None);
+    }
+}
@@ -0,0 +1,245 @@
+use shared::{CoordinateSpace, Location};
+use std::collections::{HashMap, VecDeque};
+
+/// How remote cursors are smoothed between network updates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InterpolationConfig {
+    /// How far behind the newest known position cursors are rendered, in ms.
+    pub delay_ms: f64,
+    /// How long a cursor may keep moving past its last known position, in ms.
+    /// It then eases back to that position over the same time.
+    pub max_extrapolation_ms: f64,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        InterpolationConfig {
+            delay_ms: 100.0,
+            max_extrapolation_ms: 50.0,
+        }
+    }
+}
+
+/// Upper bound on buffered samples per peer.
+const MAX_SAMPLES: usize = 32;
+
+/// How quickly a peer's clock offset follows samples that arrived late.
+const CLOCK_OFFSET_RELAX: f64 = 0.01;
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    /// Local time the sample represents, after clock offset correction.
+    time: f64,
+    x: f32,
+    y: f32,
+}
+
+#[derive(Default)]
+struct PeerTrack {
+    samples: VecDeque<Sample>,
+    last_seq: Option<u32>,
+    /// Estimated `local clock - peer clock`, tracking the fastest delivery seen.
+    clock_offset: Option<f64>,
+    space: Option<CoordinateSpace>,
+    last_emitted: Option<(f32, f32)>,
+}
+
+impl PeerTrack {
+    fn push(&mut self, location: &Location, received_at: f64) {
+        if let Some(last_seq) = self.last_seq {
+            if (location.seq.wrapping_sub(last_seq) as i32) <= 0 {
+                return; // out of order or duplicate
+            }
+        }
+        self.last_seq = Some(location.seq);
+
+        if self.space != Some(location.space) {
+            // positions in different spaces can't be blended
+            self.samples.clear();
+            self.space = Some(location.space);
+        }
+
+        let offset = received_at - location.timestamp;
+        let offset = match self.clock_offset {
+            Some(current) if offset >= current => current + (offset - current) * CLOCK_OFFSET_RELAX,
+            _ => offset,
+        };
+        self.clock_offset = Some(offset);
+
+        let time = location.timestamp + offset;
+        if let Some(last) = self.samples.back() {
+            if time < last.time {
+                // the offset estimate moved backwards, keep samples ordered
+                self.samples.clear();
+            }
+        }
+        self.samples.push_back(Sample {
+            time,
+            x: location.x,
+            y: location.y,
+        });
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    fn sample(&mut self, render_time: f64, config: &InterpolationConfig) -> Option<(f32, f32)> {
+        // drop samples we will never interpolate from again
+        while self.samples.len() > 2 && self.samples[1].time <= render_time {
+            self.samples.pop_front();
+        }
+
+        let first = self.samples.front()?;
+        if render_time <= first.time || self.samples.len() == 1 {
+            return Some((first.x, first.y));
+        }
+
+        let second = self.samples[1];
+        if render_time <= second.time {
+            let t = ((render_time - first.time) / (second.time - first.time)) as f32;
+            return Some(lerp(first, &second, t));
+        }
+
+        // render time is past everything we know about, extrapolate briefly
+        // and settle back where the peer stopped, nothing else will move us
+        let span = second.time - first.time;
+        let max = config.max_extrapolation_ms;
+        if span <= 0.0 || max <= 0.0 {
+            return Some((second.x, second.y));
+        }
+        let ahead = render_time - second.time;
+        let overshoot = 1.0 + (ahead.min(max) / span) as f32;
+        let back = ((ahead - max) / max).clamp(0.0, 1.0) as f32;
+        let t = overshoot + (1.0 - overshoot) * back;
+        Some(lerp(first, &second, t))
+    }
+}
+
+fn lerp(a: &Sample, b: &Sample, t: f32) -> (f32, f32) {
+    (a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t)
+}
+
+/// Buffers timestamped remote positions and produces smoothed positions per frame.
+pub struct Interpolator {
+    config: InterpolationConfig,
+    peers: HashMap<u64, PeerTrack>,
+}
+
+impl Interpolator {
+    pub fn new(config: InterpolationConfig) -> Interpolator {
+        Interpolator {
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: InterpolationConfig) {
+        self.config = config;
+    }
+
+    /// Records a location received from the server at local time `received_at`.
+    pub fn push(&mut self, location: &Location, received_at: f64) {
+        self.peers
+            .entry(location.id)
+            .or_default()
+            .push(location, received_at);
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        self.peers.remove(&id);
+    }
+
+    /// Returns the interpolated position of every peer that moved since the
+    /// last frame, or of every peer when `all` is set.
+    pub fn frame(&mut self, now: f64, all: bool) -> Vec<(u64, f32, f32, CoordinateSpace)> {
+        let render_time = now - self.config.delay_ms;
+        let mut moved = Vec::new();
+        for (id, track) in self.peers.iter_mut() {
+            if let (Some(position), Some(space)) =
+                (track.sample(render_time, &self.config), track.space)
+            {
+                if all || track.last_emitted != Some(position) {
+                    track.last_emitted = Some(position);
+                    moved.push((*id, position.0, position.1, space));
+                }
+            }
+        }
+        moved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(seq: u32, timestamp: f64, x: f32) -> Location {
+        Location {
+            id: 1,
+            x,
+            y: 0.0,
+            seq,
+            timestamp,
+            space: CoordinateSpace::Document,
+            anchor: None,
+        }
+    }
+
+    fn config() -> InterpolationConfig {
+        InterpolationConfig {
+            delay_ms: 100.0,
+            max_extrapolation_ms: 50.0,
+        }
+    }
+
+    #[test]
+    fn interpolates_between_samples() {
+        let mut interpolator = Interpolator::new(config());
+        interpolator.push(&location(1, 0.0, 0.0), 1000.0);
+        interpolator.push(&location(2, 100.0, 1.0), 1100.0);
+
+        let moved = interpolator.frame(1150.0, false);
+
+        assert_eq!(moved, vec![(1, 0.5, 0.0, CoordinateSpace::Document)]);
+    }
+
+    #[test]
+    fn extrapolation_settles_at_the_last_sample() {
+        let mut interpolator = Interpolator::new(config());
+        interpolator.push(&location(1, 0.0, 0.0), 1000.0);
+        interpolator.push(&location(2, 100.0, 1.0), 1100.0);
+
+        assert_eq!(
+            interpolator.frame(1250.0, false),
+            vec![(1, 1.5, 0.0, CoordinateSpace::Document)]
+        );
+        assert_eq!(
+            interpolator.frame(1275.0, false),
+            vec![(1, 1.25, 0.0, CoordinateSpace::Document)]
+        );
+        assert_eq!(
+            interpolator.frame(1500.0, false),
+            vec![(1, 1.0, 0.0, CoordinateSpace::Document)]
+        );
+    }
+
+    #[test]
+    fn stale_sequence_numbers_are_dropped() {
+        let mut interpolator = Interpolator::new(config());
+        interpolator.push(&location(2, 100.0, 1.0), 1100.0);
+        interpolator.push(&location(1, 0.0, 0.0), 1101.0);
+
+        let moved = interpolator.frame(2000.0, false);
+
+        assert_eq!(moved, vec![(1, 1.0, 0.0, CoordinateSpace::Document)]);
+    }
+
+    #[test]
+    fn unchanged_positions_are_not_reported() {
+        let mut interpolator = Interpolator::new(config());
+        interpolator.push(&location(1, 0.0, 0.5), 1000.0);
+
+        assert_eq!(interpolator.frame(1200.0, false).len(), 1);
+        assert!(interpolator.frame(1300.0, false).is_empty());
+        assert_eq!(interpolator.frame(1400.0, true).len(), 1);
+    }
+}
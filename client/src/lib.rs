@@ -1,11 +1,39 @@
+mod anchor;
+mod drawing;
+mod interpolation;
+mod reconnect;
+mod renderer;
+mod selection;
+mod site_policy;
+mod throttle;
+mod viewport;
+
+use drawing::Sketch;
+use interpolation::{InterpolationConfig, Interpolator};
 use rand::prelude::*;
-use shared::{Auth, Despawn, Location, Message, MessageType, Spawn};
+use reconnect::Reconnect;
+use renderer::{CallbackRenderer, DomRenderer, Renderer};
+use shared::{
+    Annotation, AnnotationChange, AnnotationSync, Auth, AuthAck, BlockList, Chat, ChatHistory,
+    ChatHistoryRequest, ChatMessage, CoordinateSpace, Despawn, Follow, GoingAway, Location, LwwMap,
+    Message, MessageType, Moderation, ModerationAction, PagePolicy, Ping, PingKind, Presence,
+    Reaction, RoomConfig, RoomStateUpdate, Spawn, StrokeAppend, StrokeEnd, StrokeErase,
+    StrokePoint, StrokeStart, StrokeSync, TextQuote, TextSelection, Viewport, MAX_REACTION_LENGTH,
+    MAX_STATE_KEY_LENGTH, MAX_STATE_VALUE_LENGTH, POLICY_META_NAME, POLICY_WELL_KNOWN_PATH,
+};
+use site_policy::{Category, SiteSettings};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use throttle::{OutboundThrottle, ThrottleConfig};
+use viewport::PageMetrics;
 use wasm_bindgen::prelude::*;
-use web_sys::{ErrorEvent, MessageEvent, WebSocket};
+use web_sys::{CloseEvent, ErrorEvent, MessageEvent, WebSocket};
 
 macro_rules! console_log {
-    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => ($crate::log(&format_args!($($t)*).to_string()))
 }
+pub(crate) use console_log;
 
 #[wasm_bindgen]
 extern "C" {
@@ -17,37 +45,1582 @@ extern "C" {
 
 #[wasm_bindgen]
 pub struct WebSocketSend {
-    websocket: WebSocket,
+    websocket: Socket,
+    state: Rc<RefCell<ClientState>>,
 }
 
-#[wasm_bindgen]
-pub unsafe fn send_location(wss: &mut WebSocketSend, x: f32, y: f32) {
-    let location = Location { id: 123, x, y };
-    let message = Message {
-        message_type: MessageType::MyLocation,
-        data: location.serialize().unwrap(),
-    };
+/// The socket we are connected with, replaced whenever we reconnect.
+#[derive(Clone)]
+struct Socket(Rc<RefCell<WebSocket>>);
+
+impl Socket {
+    fn get(&self) -> WebSocket {
+        self.0.borrow().clone()
+    }
+}
+
+const SERVER_URL: &str = "wss://multiplayer-web.fly.dev/ws/";
+
+/// Close code the server uses when it removes us on purpose.
+const CLOSE_POLICY: u16 = 1008;
+/// Close code the server uses when it can't take us yet, e.g. our id is
+/// still held by our previous socket.
+const CLOSE_TRY_AGAIN: u16 = 1013;
+
+/// State shared between the socket callbacks and the animation frame loop.
+struct ClientState {
+    /// Our own id, sent with `Auth`.
+    user_id: u64,
+    /// Where we connect to, the relay of the page's policy or ours.
+    server_url: String,
+    /// Limits of the page's policy, applied by the server if we own the room.
+    room_config: RoomConfig,
+    renderer: Box<dyn Renderer>,
+    /// Peers in the room and their icons, spawned unless blocked.
+    peers: HashMap<u64, String>,
+    reconnect: Reconnect,
+    interpolator: Interpolator,
+    throttle: OutboundThrottle,
+    seq: u32,
+    /// Page metrics remote cursors were last rendered with.
+    metrics: Option<PageMetrics>,
+    /// Page metrics as last read, reading them forces a layout.
+    page_metrics: Option<PageMetrics>,
+    /// Set by scroll and resize events when `page_metrics` needs reading again.
+    metrics_stale: Rc<Cell<bool>>,
+    /// Last local pointer position in viewport pixels, re-sent when the page scrolls.
+    pointer: Option<(f64, f64)>,
+    /// Whether outgoing positions carry the element under the pointer.
+    anchoring: bool,
+    /// Set when our scroll position or size changed and peers haven't been told yet.
+    viewport_dirty: bool,
+    viewport_sent_at: f64,
+    peer_viewports: HashMap<u64, Viewport>,
+    viewport_callback: Option<js_sys::Function>,
+    peer_presence: HashMap<u64, Presence>,
+    presence_callback: Option<js_sys::Function>,
+    /// Peer whose viewport we mirror.
+    following: Option<u64>,
+    /// Scroll offset we last moved to while following, to detect manual scrolling.
+    follow_scroll: Option<(f64, f64)>,
+    chat_callback: Option<js_sys::Function>,
+    typing_sent_at: f64,
+    chat_message_callback: Option<js_sys::Function>,
+    chat_history_callback: Option<js_sys::Function>,
+    /// Oldest and newest chat log `seq` handed to the page. The newest page
+    /// comes again whenever we rejoin, only what we missed is handed over.
+    chat_seen: Option<(u64, u64)>,
+    ping_callback: Option<js_sys::Function>,
+    reaction_callback: Option<js_sys::Function>,
+    /// Set when our selection may have changed since it was last shared.
+    selection_dirty: bool,
+    selection_sent_at: f64,
+    sent_selection: Option<TextQuote>,
+    /// Peer selections found in our page.
+    peer_selections: HashMap<u64, web_sys::Range>,
+    selection_callback: Option<js_sys::Function>,
+    /// The room's annotations by id.
+    annotations: HashMap<u64, Annotation>,
+    annotation_callback: Option<js_sys::Function>,
+    sketch: Sketch,
+    stroke_sent_at: f64,
+    /// Our replica of the room's key-value state, values are JSON.
+    room_state: LwwMap,
+    state_observers: Vec<js_sys::Function>,
+    /// Who runs the room, us included.
+    owner: Option<u64>,
+    /// Peers the owner muted.
+    muted: HashSet<u64>,
+    moderation_callback: Option<js_sys::Function>,
+    /// Peers the user hid on this site.
+    blocked: BlockList,
+    /// Whether the server is told about `blocked`, to skip sending us their traffic.
+    share_blocked: bool,
+    block_list_callback: Option<js_sys::Function>,
+    /// Set by `stop_websocket`, we stay disconnected from then on.
+    stopped: bool,
+    /// Peer whose instance of the room we ask to join.
+    invited_by: Option<u64>,
+    /// Instance of the room the server put us in, once joined.
+    instance: Option<u32>,
+}
+
+/// Minimum time between two typing-in-progress chat updates, in ms.
+const TYPING_INTERVAL_MS: f64 = 250.0;
+
+/// How far (in px) the page may be from where following put it before we
+/// assume the user scrolled.
+const FOLLOW_SCROLL_TOLERANCE: f64 = 2.0;
+
+/// Minimum time between two viewport updates, in ms.
+const VIEWPORT_INTERVAL_MS: f64 = 100.0;
+
+/// Minimum time between two selection updates, in ms.
+const SELECTION_INTERVAL_MS: f64 = 200.0;
+
+/// Minimum time between two batches of points of the stroke we draw, in ms.
+const STROKE_INTERVAL_MS: f64 = 50.0;
 
+/// Milliseconds on the page's monotonic clock.
+pub(crate) fn now() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map(|performance| performance.now())
+        .unwrap_or_else(js_sys::Date::now)
+}
+
+type FrameCallback = Rc<RefCell<Option<Closure<dyn FnMut(f64)>>>>;
+
+fn request_animation_frame(f: &Closure<dyn FnMut(f64)>) {
+    if let Some(window) = web_sys::window() {
+        let _ = window.request_animation_frame(f.as_ref().unchecked_ref());
+    }
+}
+
+fn send_message(ws: &WebSocket, message: &Message) {
+    if ws.ready_state() != WebSocket::OPEN {
+        return; // dropped while we reconnect, the server resends what matters on join
+    }
     if let Ok(data) = message.serialize() {
-        let res = wss.websocket.send_with_u8_array(&data);
+        let res = ws.send_with_u8_array(&data);
         if let Err(e) = res {
             console_log!("{:?}", e);
         }
     }
 }
 
+/// Decodes the payload of `message`, dropping it with a log line when it is
+/// malformed rather than taking the client down.
+fn decode<T, E: std::fmt::Debug>(
+    message: &Message,
+    deserialize: impl Fn(&[u8]) -> Result<T, E>,
+) -> Option<T> {
+    match deserialize(&message.data) {
+        Ok(payload) => Some(payload),
+        Err(e) => {
+            console_log!("{:?} - Failed to decode {:?}.", e, message.message_type);
+            None
+        }
+    }
+}
+
+/// Sends the latest local position if the throttle allows it.
+fn flush_location(ws: &WebSocket, state: &mut ClientState, now: f64) {
+    if ws.ready_state() != WebSocket::OPEN {
+        return; // keep the position pending until we can send it
+    }
+    if let Some(pending) = state.throttle.poll(now) {
+        let anchor = match (state.anchoring, pending.space, state.pointer) {
+            (true, CoordinateSpace::Document, Some((client_x, client_y))) => {
+                anchor::anchor_at(client_x, client_y)
+            }
+            _ => None,
+        };
+        state.seq = state.seq.wrapping_add(1);
+        let location = Location {
+            id: 123,
+            x: pending.x,
+            y: pending.y,
+            seq: state.seq,
+            timestamp: pending.sampled_at,
+            space: pending.space,
+            anchor,
+        };
+        let message = Message {
+            message_type: MessageType::MyLocation,
+            data: location.serialize().unwrap(),
+        };
+        send_message(ws, &message);
+    }
+}
+
+/// Records the local cursor position as fractions of the viewport. Positions
+/// are coalesced and sent from the animation frame loop, see `set_throttle`.
+#[wasm_bindgen]
+pub fn send_location(wss: &mut WebSocketSend, x: f32, y: f32) {
+    let mut state = wss.state.borrow_mut();
+    let now = now();
+    state.pointer = None;
+    state.throttle.push(x, y, CoordinateSpace::Viewport, now);
+    flush_location(&wss.websocket.get(), &mut state, now);
+}
+
+/// Records the local cursor position in viewport pixels, e.g. `clientX` and
+/// `clientY` of a mouse event. The position is shared relative to the whole
+/// document, so peers see it at the same spot regardless of scroll and size.
+#[wasm_bindgen]
+pub fn send_pointer(wss: &mut WebSocketSend, client_x: f64, client_y: f64) {
+    let mut state = wss.state.borrow_mut();
+    let now = now();
+    state.pointer = Some((client_x, client_y));
+    if let Some(metrics) = page_metrics(&mut state) {
+        let (x, y) = metrics.document_position(client_x, client_y);
+        state.throttle.push(x, y, CoordinateSpace::Document, now);
+    }
+    flush_location(&wss.websocket.get(), &mut state, now);
+}
+
+/// Enables sharing the element under the pointer along with its document
+/// position, so peers with a different layout see the cursor over the same
+/// content. Only applies to positions sent with `send_pointer`.
+#[wasm_bindgen]
+pub fn set_element_anchoring(wss: &WebSocketSend, enabled: bool) {
+    wss.state.borrow_mut().anchoring = enabled;
+}
+
+/// Tells peers which part of the page we are looking at, at most every
+/// `VIEWPORT_INTERVAL_MS`.
+fn flush_viewport(ws: &WebSocket, state: &mut ClientState, now: f64) {
+    if !state.viewport_dirty
+        || ws.ready_state() != WebSocket::OPEN
+        || now - state.viewport_sent_at < VIEWPORT_INTERVAL_MS
+    {
+        return;
+    }
+    if let Some(metrics) = state.metrics {
+        let message = Message {
+            message_type: MessageType::MyViewport,
+            data: metrics.viewport().serialize().unwrap(),
+        };
+        send_message(ws, &message);
+        state.viewport_dirty = false;
+        state.viewport_sent_at = now;
+    }
+}
+
+fn viewport_to_js(viewport: &Viewport) -> JsValue {
+    let object = js_sys::Object::new();
+    for (key, value) in [
+        ("id", JsValue::from(viewport.id)),
+        ("scrollX", JsValue::from(viewport.scroll_x)),
+        ("scrollY", JsValue::from(viewport.scroll_y)),
+        ("left", JsValue::from(viewport.left)),
+        ("top", JsValue::from(viewport.top)),
+        ("width", JsValue::from(viewport.width)),
+        ("height", JsValue::from(viewport.height)),
+    ] {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from(key), &value);
+    }
+    object.into()
+}
+
+/// Registers `callback(id, viewport)` to be called whenever a peer scrolls or
+/// resizes. `viewport` has `scrollX`/`scrollY` in pixels and `left`, `top`,
+/// `width`, `height` as fractions of the document, and is `null` once the
+/// peer leaves.
+#[wasm_bindgen]
+pub fn set_viewport_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().viewport_callback = Some(callback);
+}
+
+/// Returns the last known viewport of every peer, e.g. to draw a minimap.
+#[wasm_bindgen]
+pub fn peer_viewports(wss: &WebSocketSend) -> js_sys::Array {
+    wss.state
+        .borrow()
+        .peer_viewports
+        .values()
+        .map(viewport_to_js)
+        .collect()
+}
+
+fn notify_presence(state: &ClientState, presence: &Presence) {
+    if let Some(callback) = &state.presence_callback {
+        let following = match presence.following {
+            Some(id) => JsValue::from(id),
+            None => JsValue::null(),
+        };
+        let _ = callback.call2(&JsValue::null(), &JsValue::from(presence.id), &following);
+    }
+}
+
+fn set_following(ws: &WebSocket, state: &mut ClientState, target: Option<u64>) {
+    if state.following == target {
+        return;
+    }
+    state.following = target;
+    state.follow_scroll = None;
+
+    let message = Message {
+        message_type: MessageType::Follow,
+        data: Follow { target }.serialize().unwrap(),
+    };
+    send_message(ws, &message);
+
+    let presence = Presence {
+        id: state.user_id,
+        following: target,
+    };
+    notify_presence(state, &presence);
+}
+
+/// Scrolls the page to the followed peer's viewport, and stops following as
+/// soon as the user scrolls on their own.
+fn follow_frame(ws: &WebSocket, state: &mut ClientState) {
+    let (Some(target), Some(metrics)) = (state.following, state.metrics) else {
+        return;
+    };
+
+    if let Some((x, y)) = state.follow_scroll {
+        if (metrics.scroll_x - x).abs() > FOLLOW_SCROLL_TOLERANCE
+            || (metrics.scroll_y - y).abs() > FOLLOW_SCROLL_TOLERANCE
+        {
+            set_following(ws, state, None);
+            return;
+        }
+    }
+
+    let Some(viewport) = state.peer_viewports.get(&target) else {
+        return;
+    };
+    let (x, y) = metrics.scroll_to_match(viewport);
+    if (metrics.scroll_x - x).abs() > FOLLOW_SCROLL_TOLERANCE
+        || (metrics.scroll_y - y).abs() > FOLLOW_SCROLL_TOLERANCE
+    {
+        if let Some(window) = web_sys::window() {
+            // instant, a smooth scroll would look like the user scrolling
+            let options = web_sys::ScrollToOptions::new();
+            options.set_left(x);
+            options.set_top(y);
+            options.set_behavior(web_sys::ScrollBehavior::Instant);
+            window.scroll_to_with_scroll_to_options(&options);
+        }
+    }
+    state.follow_scroll = Some((x, y));
+}
+
+/// Starts mirroring `peer_id`'s scroll position until the user scrolls
+/// manually or calls `unfollow`. The room is told, so the peer knows.
+#[wasm_bindgen]
+pub fn follow(wss: &WebSocketSend, peer_id: u64) {
+    set_following(
+        &wss.websocket.get(),
+        &mut wss.state.borrow_mut(),
+        Some(peer_id),
+    );
+}
+
+#[wasm_bindgen]
+pub fn unfollow(wss: &WebSocketSend) {
+    set_following(&wss.websocket.get(), &mut wss.state.borrow_mut(), None);
+}
+
+/// The peer currently being followed, if any.
+#[wasm_bindgen]
+pub fn following(wss: &WebSocketSend) -> Option<u64> {
+    wss.state.borrow().following
+}
+
+/// Ids of the peers currently following us.
+#[wasm_bindgen]
+pub fn followers(wss: &WebSocketSend) -> js_sys::Array {
+    let state = wss.state.borrow();
+    state
+        .peer_presence
+        .values()
+        .filter(|presence| presence.following == Some(state.user_id))
+        .map(|presence| JsValue::from(presence.id))
+        .collect()
+}
+
+/// Registers `callback(id, following)` to be called when a peer starts or
+/// stops following someone (`following` is `null` when they stop). It is
+/// also called with our own id when following starts or stops locally.
+#[wasm_bindgen]
+pub fn set_presence_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().presence_callback = Some(callback);
+}
+
+/// Shows `text` in a bubble next to our cursor for everyone in the room.
+/// Call with `typing` set while the user is still writing; in-progress
+/// updates are rate limited, the final one (`typing` unset) is always sent.
+/// Sending an empty, finished message removes the bubble.
+#[wasm_bindgen]
+pub fn send_chat(wss: &WebSocketSend, text: &str, typing: bool) {
+    let mut state = wss.state.borrow_mut();
+    let now = now();
+    if typing && now - state.typing_sent_at < TYPING_INTERVAL_MS {
+        return;
+    }
+    state.typing_sent_at = if typing { now } else { f64::NEG_INFINITY };
+
+    let mut chat = Chat {
+        id: state.user_id,
+        text: String::from(text),
+        typing,
+    };
+    chat.truncate();
+    let message = Message {
+        message_type: MessageType::MyChat,
+        data: chat.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+}
+
+/// Registers `callback(id, text, typing)` to be called for every chat update
+/// from a peer, in addition to the built-in renderer's bubbles.
+#[wasm_bindgen]
+pub fn set_chat_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().chat_callback = Some(callback);
+}
+
+fn chat_message_to_js(chat_message: &ChatMessage) -> JsValue {
+    let object = js_sys::Object::new();
+    for (key, value) in [
+        ("seq", JsValue::from(chat_message.seq)),
+        ("id", JsValue::from(chat_message.id)),
+        ("text", JsValue::from(chat_message.text.as_str())),
+        ("timestamp", JsValue::from(chat_message.timestamp as f64)),
+    ] {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from(key), &value);
+    }
+    object.into()
+}
+
+/// Posts `text` to the room's chat log. It comes back through the chat
+/// message callback once the server has logged it.
+#[wasm_bindgen]
+pub fn send_chat_message(wss: &WebSocketSend, text: &str) {
+    let mut chat_message = ChatMessage {
+        seq: 0,
+        id: wss.state.borrow().user_id,
+        text: String::from(text),
+        timestamp: 0,
+    };
+    chat_message.truncate();
+    let message = Message {
+        message_type: MessageType::MyChatMessage,
+        data: chat_message.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+}
+
+/// Asks for up to `limit` chat log messages older than `before` (a message
+/// `seq`), or the newest ones when `before` is undefined. The page arrives
+/// through the chat history callback.
+#[wasm_bindgen]
+pub fn request_chat_history(wss: &WebSocketSend, before: Option<u64>, limit: u32) {
+    let request = ChatHistoryRequest { before, limit };
+    let message = Message {
+        message_type: MessageType::ChatHistoryRequest,
+        data: request.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+}
+
+/// Registers `callback(message)` for every message added to the chat log,
+/// our own included. `message` has `seq`, `id`, `text` and `timestamp`.
+#[wasm_bindgen]
+pub fn set_chat_message_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().chat_message_callback = Some(callback);
+}
+
+/// Registers `callback(messages, has_more)` for pages of chat log history,
+/// oldest first. The newest page is sent right after joining, after
+/// rejoining only with the messages missed in between.
+#[wasm_bindgen]
+pub fn set_chat_history_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().chat_history_callback = Some(callback);
+}
+
+/// Pings the spot at `client_x`/`client_y` (viewport pixels) for everyone in
+/// the room. `kind` is one of `"ripple"`, `"look"` or `"warning"`. The effect
+/// is also shown locally right away.
+#[wasm_bindgen]
+pub fn send_ping(
+    wss: &WebSocketSend,
+    client_x: f64,
+    client_y: f64,
+    kind: &str,
+) -> Result<(), JsValue> {
+    let kind = PingKind::from_name(kind)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown ping kind {:?}", kind)))?;
+    let mut state = wss.state.borrow_mut();
+    let Some(metrics) = page_metrics(&mut state) else {
+        return Ok(());
+    };
+    let (x, y) = metrics.document_position(client_x, client_y);
+
+    let ping = Ping {
+        id: state.user_id,
+        x,
+        y,
+        space: CoordinateSpace::Document,
+        kind,
+    };
+    let (x, y) = metrics.viewport_position(x, y, CoordinateSpace::Document);
+    let user_id = state.user_id;
+    state.renderer.ping(user_id, x, y, kind);
+
+    let message = Message {
+        message_type: MessageType::MyPing,
+        data: ping.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+    Ok(())
+}
+
+/// Registers `callback(id, x, y, kind)` for pings from peers, with `x` and
+/// `y` as fractions of our viewport (outside `0..1` when off-screen).
+#[wasm_bindgen]
+pub fn set_ping_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().ping_callback = Some(callback);
+}
+
+/// Floats `emoji` up from `client_x`/`client_y` (viewport pixels) for
+/// everyone in the room. The server only relays emoji from its allowlist.
+#[wasm_bindgen]
+pub fn send_reaction(wss: &WebSocketSend, client_x: f64, client_y: f64, emoji: &str) {
+    if emoji.is_empty() || emoji.len() > MAX_REACTION_LENGTH {
+        return;
+    }
+    let mut state = wss.state.borrow_mut();
+    let Some(metrics) = page_metrics(&mut state) else {
+        return;
+    };
+    let (x, y) = metrics.document_position(client_x, client_y);
+
+    let reaction = Reaction {
+        id: state.user_id,
+        x,
+        y,
+        space: CoordinateSpace::Document,
+        emoji: String::from(emoji),
+    };
+    let (x, y) = metrics.viewport_position(x, y, CoordinateSpace::Document);
+    let user_id = state.user_id;
+    state.renderer.reaction(user_id, x, y, emoji);
+
+    let message = Message {
+        message_type: MessageType::MyReaction,
+        data: reaction.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+}
+
+/// Registers `callback(id, x, y, emoji)` for reactions from peers, with `x`
+/// and `y` as fractions of our viewport.
+#[wasm_bindgen]
+pub fn set_reaction_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().reaction_callback = Some(callback);
+}
+
+/// Shares the page's text selection with the room, call it on `selectionchange`.
+/// Updates are sent at most every `SELECTION_INTERVAL_MS` and only when the
+/// selected text changed.
+#[wasm_bindgen]
+pub fn send_selection(wss: &WebSocketSend) {
+    wss.state.borrow_mut().selection_dirty = true;
+}
+
+fn flush_selection(ws: &WebSocket, state: &mut ClientState, now: f64) {
+    if !state.selection_dirty
+        || ws.ready_state() != WebSocket::OPEN
+        || now - state.selection_sent_at < SELECTION_INTERVAL_MS
+    {
+        return;
+    }
+    state.selection_dirty = false;
+
+    let quote = selection::current_quote();
+    if quote == state.sent_selection {
+        return;
+    }
+    state.selection_sent_at = now;
+    state.sent_selection = quote.clone();
+
+    let selection = TextSelection {
+        id: state.user_id,
+        quote,
+    };
+    let message = Message {
+        message_type: MessageType::MySelection,
+        data: selection.serialize().unwrap(),
+    };
+    send_message(ws, &message);
+}
+
+/// Redraws the highlight of peer `id`'s selection.
+fn render_selection(state: &mut ClientState, id: u64, metrics: &PageMetrics) {
+    let rects = state
+        .peer_selections
+        .get(&id)
+        .map(|range| selection::highlight_rects(range, metrics))
+        .unwrap_or_default();
+    state.renderer.highlight(id, &rects);
+}
+
+/// Registers `callback(id, text)` for selection changes of peers, with
+/// `text` null once a selection is cleared.
+#[wasm_bindgen]
+pub fn set_selection_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().selection_callback = Some(callback);
+}
+
+fn annotation_to_js(annotation: &Annotation) -> JsValue {
+    let object = js_sys::Object::new();
+    for (key, value) in [
+        ("id", JsValue::from(annotation.id)),
+        ("author", JsValue::from(annotation.author)),
+        ("text", JsValue::from(annotation.text.as_str())),
+        ("createdAt", JsValue::from(annotation.created_at as f64)),
+        ("updatedAt", JsValue::from(annotation.updated_at as f64)),
+    ] {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from(key), &value);
+    }
+    object.into()
+}
+
+fn send_annotation_change(ws: &WebSocket, change: &AnnotationChange) {
+    let message = Message {
+        message_type: MessageType::MyAnnotation,
+        data: change.serialize().unwrap(),
+    };
+    send_message(ws, &message);
+}
+
+/// Leaves a sticky note with `text` at `client_x`/`client_y` (viewport
+/// pixels) for everyone visiting the page, now and later. It is attached to
+/// the element under that point when possible.
+#[wasm_bindgen]
+pub fn create_annotation(wss: &WebSocketSend, client_x: f64, client_y: f64, text: &str) {
+    let Some(metrics) = page_metrics(&mut wss.state.borrow_mut()) else {
+        return;
+    };
+    let (x, y) = metrics.document_position(client_x, client_y);
+    let mut annotation = Annotation {
+        id: 0,
+        author: wss.state.borrow().user_id,
+        x,
+        y,
+        space: CoordinateSpace::Document,
+        anchor: anchor::anchor_at(client_x, client_y),
+        text: String::from(text),
+        created_at: 0,
+        updated_at: 0,
+    };
+    annotation.truncate();
+    send_annotation_change(&wss.websocket.get(), &AnnotationChange::Upsert(annotation));
+}
+
+/// Replaces the text of one of our annotations.
+#[wasm_bindgen]
+pub fn update_annotation(wss: &WebSocketSend, id: u64, text: &str) {
+    let Some(mut annotation) = wss.state.borrow().annotations.get(&id).cloned() else {
+        return;
+    };
+    annotation.text = String::from(text);
+    annotation.truncate();
+    send_annotation_change(&wss.websocket.get(), &AnnotationChange::Upsert(annotation));
+}
+
+/// Deletes one of our annotations.
+#[wasm_bindgen]
+pub fn delete_annotation(wss: &WebSocketSend, id: u64) {
+    send_annotation_change(&wss.websocket.get(), &AnnotationChange::Delete(id));
+}
+
+/// The room's annotations, see `set_annotation_callback` for their fields.
+#[wasm_bindgen]
+pub fn annotations(wss: &WebSocketSend) -> js_sys::Array {
+    wss.state
+        .borrow()
+        .annotations
+        .values()
+        .map(annotation_to_js)
+        .collect()
+}
+
+/// Registers `callback(id, annotation)` for every annotation that is added
+/// or changed, ours included, and with `annotation` null once it is deleted.
+/// `annotation` has `id`, `author`, `text`, `createdAt` and `updatedAt`.
+/// Only the author may change or delete an annotation, also after a reload
+/// as long as they connect from the same address.
+#[wasm_bindgen]
+pub fn set_annotation_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().annotation_callback = Some(callback);
+}
+
+/// Draws annotation `id` where it is in our viewport, following its anchor
+/// if the anchored element is on this page.
+fn render_annotation(state: &mut ClientState, id: u64, metrics: &PageMetrics) {
+    let ClientState {
+        annotations,
+        renderer,
+        ..
+    } = state;
+    let Some(annotation) = annotations.get(&id) else {
+        renderer.annotation(id, None);
+        return;
+    };
+    let (x, y) = annotation
+        .anchor
+        .as_ref()
+        .and_then(|anchor| anchor::resolve(anchor, metrics))
+        .map(|(x, y)| metrics.viewport_position(x, y, CoordinateSpace::Document))
+        .unwrap_or_else(|| metrics.viewport_position(annotation.x, annotation.y, annotation.space));
+    renderer.annotation(id, Some((x, y, annotation)));
+}
+
+fn apply_annotation_change(state: &mut ClientState, change: AnnotationChange) {
+    let (id, annotation) = match change {
+        AnnotationChange::Upsert(annotation) => {
+            let id = annotation.id;
+            state.annotations.insert(id, annotation);
+            (id, state.annotations.get(&id).map(annotation_to_js))
+        }
+        AnnotationChange::Delete(id) => {
+            state.annotations.remove(&id);
+            (id, None)
+        }
+    };
+    if let Some(metrics) = page_metrics(state) {
+        render_annotation(state, id, &metrics);
+    }
+    if let Some(callback) = &state.annotation_callback {
+        let _ = callback.call2(
+            &JsValue::null(),
+            &JsValue::from(id),
+            &annotation.unwrap_or(JsValue::null()),
+        );
+    }
+}
+
+fn send_stroke_end(ws: &WebSocket, state: &mut ClientState) {
+    if let Some(append) = state.sketch.take_pending() {
+        let message = Message {
+            message_type: MessageType::MyStrokeAppend,
+            data: append.serialize().unwrap(),
+        };
+        send_message(ws, &message);
+    }
+    if let Some(end) = state.sketch.end() {
+        let message = Message {
+            message_type: MessageType::MyStrokeEnd,
+            data: end.serialize().unwrap(),
+        };
+        send_message(ws, &message);
+    }
+}
+
+/// Starts drawing a stroke at `client_x`/`client_y` (viewport pixels) in
+/// `color` (`#rrggbb`) and `width` CSS pixels, visible to everyone in the room.
 #[wasm_bindgen]
-pub unsafe fn start_websocket(
+pub fn start_stroke(
+    wss: &WebSocketSend,
+    client_x: f64,
+    client_y: f64,
+    color: &str,
+    width: f32,
+) -> Result<(), JsValue> {
+    let color = drawing::parse_color(color)
+        .ok_or_else(|| JsValue::from_str(&format!("expected a #rrggbb color, got {:?}", color)))?;
+    let mut state = wss.state.borrow_mut();
+    send_stroke_end(&wss.websocket.get(), &mut state);
+
+    let start = state.sketch.start(color, width);
+    let message = Message {
+        message_type: MessageType::MyStrokeStart,
+        data: start.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+    drop(state);
+
+    extend_stroke(wss, client_x, client_y);
+    Ok(())
+}
+
+/// Adds a point to the stroke being drawn. Points are sent in batches every
+/// `STROKE_INTERVAL_MS`.
+#[wasm_bindgen]
+pub fn extend_stroke(wss: &WebSocketSend, client_x: f64, client_y: f64) {
+    let mut state = wss.state.borrow_mut();
+    if let Some(metrics) = page_metrics(&mut state) {
+        let (x, y) = metrics.document_position(client_x, client_y);
+        state.sketch.extend(StrokePoint::new(x, y));
+    }
+}
+
+/// Finishes the stroke being drawn.
+#[wasm_bindgen]
+pub fn end_stroke(wss: &WebSocketSend) {
+    send_stroke_end(&wss.websocket.get(), &mut wss.state.borrow_mut());
+}
+
+fn erase_strokes(wss: &WebSocketSend, all: bool) {
+    let mut state = wss.state.borrow_mut();
+    send_stroke_end(&wss.websocket.get(), &mut state);
+    let erase = StrokeErase {
+        id: state.user_id,
+        all,
+    };
+    state.sketch.apply_erase(&erase);
+    let message = Message {
+        message_type: MessageType::MyStrokeErase,
+        data: erase.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+}
+
+/// Removes our latest stroke.
+#[wasm_bindgen]
+pub fn undo_stroke(wss: &WebSocketSend) {
+    erase_strokes(wss, false);
+}
+
+/// Removes all of our strokes. Other users' strokes stay.
+#[wasm_bindgen]
+pub fn clear_strokes(wss: &WebSocketSend) {
+    erase_strokes(wss, true);
+}
+
+fn flush_stroke(ws: &WebSocket, state: &mut ClientState, now: f64) {
+    if ws.ready_state() != WebSocket::OPEN || now - state.stroke_sent_at < STROKE_INTERVAL_MS {
+        return;
+    }
+    if let Some(append) = state.sketch.take_pending() {
+        state.stroke_sent_at = now;
+        let message = Message {
+            message_type: MessageType::MyStrokeAppend,
+            data: append.serialize().unwrap(),
+        };
+        send_message(ws, &message);
+    }
+}
+
+fn parse_state_value(value: &str) -> JsValue {
+    js_sys::JSON::parse(value).unwrap_or(JsValue::UNDEFINED)
+}
+
+/// Tells `observers` about `update`'s entries. Called without the client
+/// state borrowed, so observers can read the state.
+fn notify_state(observers: &[js_sys::Function], update: &RoomStateUpdate) {
+    for (key, register) in &update.entries {
+        let value = register
+            .value
+            .as_deref()
+            .map(parse_state_value)
+            .unwrap_or(JsValue::UNDEFINED);
+        for observer in observers {
+            let _ = observer.call2(&JsValue::null(), &JsValue::from(key.as_str()), &value);
+        }
+    }
+}
+
+fn set_state(wss: &WebSocketSend, key: &str, value: Option<String>) -> Result<(), JsValue> {
+    if key.len() > MAX_STATE_KEY_LENGTH {
+        return Err(JsValue::from_str("room state key is too long"));
+    }
+    if value
+        .as_ref()
+        .is_some_and(|value| value.len() > MAX_STATE_VALUE_LENGTH)
+    {
+        return Err(JsValue::from_str("room state value is too large"));
+    }
+
+    let (update, observers) = {
+        let mut state = wss.state.borrow_mut();
+        let user_id = state.user_id;
+        let update = state.room_state.set(user_id, key, value);
+        (update, state.state_observers.clone())
+    };
+    let message = Message {
+        message_type: MessageType::MyStateUpdate,
+        data: update.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+    notify_state(&observers, &update);
+    Ok(())
+}
+
+/// The value of `key` in the room's shared state, or undefined.
+#[wasm_bindgen]
+pub fn state_get(wss: &WebSocketSend, key: &str) -> JsValue {
+    wss.state
+        .borrow()
+        .room_state
+        .get(key)
+        .map(parse_state_value)
+        .unwrap_or(JsValue::UNDEFINED)
+}
+
+/// Sets `key` in the room's shared state to `value`, which must be JSON
+/// serializable. Concurrent writes to a key resolve to the same value for
+/// everyone; the latest one wins.
+#[wasm_bindgen]
+pub fn state_set(wss: &WebSocketSend, key: &str, value: JsValue) -> Result<(), JsValue> {
+    let json = js_sys::JSON::stringify(&value)?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str("value is not JSON serializable"))?;
+    set_state(wss, key, Some(json))
+}
+
+/// Removes `key` from the room's shared state.
+#[wasm_bindgen]
+pub fn state_delete(wss: &WebSocketSend, key: &str) -> Result<(), JsValue> {
+    set_state(wss, key, None)
+}
+
+/// Keys set in the room's shared state.
+#[wasm_bindgen]
+pub fn state_keys(wss: &WebSocketSend) -> js_sys::Array {
+    wss.state
+        .borrow()
+        .room_state
+        .iter()
+        .map(|(key, _)| JsValue::from(key))
+        .collect()
+}
+
+/// Registers `callback(key, value)` for every change to the room's shared
+/// state, ours included, with `value` undefined once a key is deleted.
+/// Unlike the other callbacks, any number of observers can be registered.
+#[wasm_bindgen]
+pub fn observe_state(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().state_observers.push(callback);
+}
+
+/// Asks the server to apply `action` to peer `target`: `"mute"`, `"unmute"`,
+/// `"kick"`, `"ban"` (for `seconds`) or `"owner"` to hand the room over.
+/// Only the room's owner may moderate, and only peers in the room, see
+/// `room_owner`.
+#[wasm_bindgen]
+pub fn moderate(
+    wss: &WebSocketSend,
+    target: u64,
+    action: &str,
+    seconds: u32,
+) -> Result<(), JsValue> {
+    let action = ModerationAction::from_name(action)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown moderation action {:?}", action)))?;
+    let state = wss.state.borrow();
+    if state.owner != Some(state.user_id) {
+        return Err(JsValue::from_str("only the room's owner can moderate"));
+    }
+    let moderation = Moderation {
+        id: state.user_id,
+        target,
+        action,
+        seconds,
+    };
+    let message = Message {
+        message_type: MessageType::MyModeration,
+        data: moderation.serialize().unwrap(),
+    };
+    send_message(&wss.websocket.get(), &message);
+    Ok(())
+}
+
+/// Id of the user who runs the room, if known yet.
+#[wasm_bindgen]
+pub fn room_owner(wss: &WebSocketSend) -> Option<u64> {
+    wss.state.borrow().owner
+}
+
+/// Whether the owner muted user `id`: nothing they do reaches the room.
+#[wasm_bindgen]
+pub fn is_muted(wss: &WebSocketSend, id: u64) -> bool {
+    wss.state.borrow().muted.contains(&id)
+}
+
+/// Registers `callback(moderator, target, action, seconds)` for moderation
+/// in the room, including ownership changes. `moderator` is 0 when the
+/// server acted on its own, e.g. after the owner left.
+#[wasm_bindgen]
+pub fn set_moderation_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().moderation_callback = Some(callback);
+}
+
+fn apply_moderation(state: &mut ClientState, moderation: &Moderation) {
+    match moderation.action {
+        ModerationAction::Owner => state.owner = Some(moderation.target),
+        ModerationAction::Mute => {
+            state.muted.insert(moderation.target);
+        }
+        ModerationAction::Unmute => {
+            state.muted.remove(&moderation.target);
+        }
+        // the server closes their socket, they despawn as usual
+        ModerationAction::Kick | ModerationAction::Ban => {}
+    }
+    if let Some(callback) = &state.moderation_callback {
+        let args = js_sys::Array::of4(
+            &JsValue::from(moderation.id),
+            &JsValue::from(moderation.target),
+            &JsValue::from(moderation.action.name()),
+            &JsValue::from(moderation.seconds),
+        );
+        let _ = callback.apply(&JsValue::null(), &args);
+    }
+}
+
+/// Hides peer `id` on this page: their cursor, chat, pings, reactions and
+/// selection stop reaching the renderer and callbacks. Ids only last as long
+/// as the peer's session, a blocked peer is back under a new id once they
+/// reload.
+#[wasm_bindgen]
+pub fn block_peer(wss: &WebSocketSend, id: u64) {
+    let mut blocked = wss.state.borrow().blocked.clone();
+    if blocked.block(id) {
+        set_blocked(wss, blocked);
+    }
+}
+
+#[wasm_bindgen]
+pub fn unblock_peer(wss: &WebSocketSend, id: u64) {
+    let mut blocked = wss.state.borrow().blocked.clone();
+    if blocked.unblock(id) {
+        set_blocked(wss, blocked);
+    }
+}
+
+/// Hides every peer on this site, or with `hidden` false only the blocked ones.
+#[wasm_bindgen]
+pub fn hide_all_peers(wss: &WebSocketSend, hidden: bool) {
+    let mut blocked = wss.state.borrow().blocked.clone();
+    if blocked.all != hidden {
+        blocked.all = hidden;
+        set_blocked(wss, blocked);
+    }
+}
+
+/// Whether peer `id` is hidden, blocked on its own or with everyone else.
+#[wasm_bindgen]
+pub fn is_blocked(wss: &WebSocketSend, id: u64) -> bool {
+    wss.state.borrow().blocked.hides(id)
+}
+
+/// The block list as `{ users, all }`, with ids as strings so it can be
+/// passed around as JSON.
+#[wasm_bindgen]
+pub fn block_list(wss: &WebSocketSend) -> JsValue {
+    block_list_to_js(&wss.state.borrow().blocked)
+}
+
+/// Replaces the block list with one saved from `block_list` or the block
+/// list callback.
+#[wasm_bindgen]
+pub fn set_block_list(wss: &WebSocketSend, value: JsValue) -> Result<(), JsValue> {
+    let blocked =
+        block_list_from_js(&value).ok_or_else(|| JsValue::from_str("not a block list"))?;
+    set_blocked(wss, blocked);
+    Ok(())
+}
+
+/// Registers `callback(list)` for changes of the block list, in the format
+/// of `block_list`. Only `all` is worth keeping past this page, `users`
+/// are ids of the current sessions.
+#[wasm_bindgen]
+pub fn set_block_list_callback(wss: &WebSocketSend, callback: js_sys::Function) {
+    wss.state.borrow_mut().block_list_callback = Some(callback);
+}
+
+/// Whether the server is told who we hide, so it stops sending us their
+/// cursors, chat and effects to save bandwidth. Off by default.
+#[wasm_bindgen]
+pub fn set_block_list_sharing(wss: &WebSocketSend, enabled: bool) {
+    let mut state = wss.state.borrow_mut();
+    if state.share_blocked == enabled {
+        return;
+    }
+    state.share_blocked = enabled;
+    // an empty list makes the server send everything again
+    let blocked = if enabled {
+        state.blocked.clone()
+    } else {
+        BlockList::default()
+    };
+    send_block_list(&wss.websocket.get(), &blocked);
+}
+
+/// Switches to `blocked`, hiding and showing peers as needed, and lets the
+/// server and the block list callback know.
+fn set_blocked(wss: &WebSocketSend, blocked: BlockList) {
+    let callback = {
+        let mut state = wss.state.borrow_mut();
+        let previous = std::mem::replace(&mut state.blocked, blocked);
+        let peers: Vec<(u64, String)> = state
+            .peers
+            .iter()
+            .map(|(id, icon)| (*id, icon.clone()))
+            .collect();
+        for (id, icon) in peers {
+            match (previous.hides(id), state.blocked.hides(id)) {
+                (false, true) => hide_peer(&mut state, id),
+                // they show up once they move again
+                (true, false) => state.renderer.spawn(id, &icon),
+                _ => {}
+            }
+        }
+        if state.share_blocked {
+            send_block_list(&wss.websocket.get(), &state.blocked);
+        }
+        state.block_list_callback.clone()
+    };
+    // called unborrowed, the callback may well read the list back
+    if let Some(callback) = callback {
+        let _ = callback.call1(&JsValue::null(), &block_list(wss));
+    }
+}
+
+/// Takes a peer off the page without forgetting they are in the room.
+fn hide_peer(state: &mut ClientState, id: u64) {
+    state.interpolator.remove(id);
+    state.renderer.despawn(id);
+    state.peer_selections.remove(&id);
+}
+
+fn send_block_list(ws: &WebSocket, blocked: &BlockList) {
+    let message = Message {
+        message_type: MessageType::MyBlockList,
+        data: blocked.serialize().unwrap(),
+    };
+    send_message(ws, &message);
+}
+
+fn block_list_to_js(blocked: &BlockList) -> JsValue {
+    let users: js_sys::Array = blocked
+        .users
+        .iter()
+        .map(|id| JsValue::from(id.to_string()))
+        .collect();
+    let object = js_sys::Object::new();
+    let _ = js_sys::Reflect::set(&object, &JsValue::from("users"), &users);
+    let _ = js_sys::Reflect::set(&object, &JsValue::from("all"), &JsValue::from(blocked.all));
+    object.into()
+}
+
+fn block_list_from_js(value: &JsValue) -> Option<BlockList> {
+    let users = js_sys::Reflect::get(value, &JsValue::from("users"))
+        .ok()?
+        .dyn_into::<js_sys::Array>()
+        .ok()?;
+    let mut blocked = BlockList {
+        users: Vec::new(),
+        all: js_sys::Reflect::get(value, &JsValue::from("all"))
+            .ok()?
+            .as_bool()
+            .unwrap_or(false),
+    };
+    for id in users
+        .iter()
+        .filter_map(|user| user.as_string()?.parse().ok())
+    {
+        blocked.block(id);
+    }
+    Some(blocked)
+}
+
+/// The page's current metrics, only read again after it scrolled or resized.
+fn page_metrics(state: &mut ClientState) -> Option<PageMetrics> {
+    if state.metrics_stale.replace(false) || state.page_metrics.is_none() {
+        state.page_metrics = PageMetrics::current();
+    }
+    state.page_metrics
+}
+
+/// Moves remote cursors to their interpolated positions, re-rendering all of
+/// them when our own scroll or size changed.
+fn render_frame(state: &mut ClientState, now: f64) {
+    let metrics = page_metrics(state);
+    let changed = metrics != state.metrics;
+    state.metrics = metrics;
+
+    if changed {
+        state.viewport_dirty = true;
+
+        // a still pointer still moves through the document while scrolling
+        if let (Some(metrics), Some((client_x, client_y))) = (metrics, state.pointer) {
+            let (x, y) = metrics.document_position(client_x, client_y);
+            state.throttle.push(x, y, CoordinateSpace::Document, now);
+        }
+    }
+
+    let Some(metrics) = metrics else {
+        return;
+    };
+    for (id, x, y, space) in state.interpolator.frame(now, changed) {
+        let (x, y) = metrics.viewport_position(x, y, space);
+        state.renderer.move_to(id, x, y);
+    }
+
+    if changed {
+        let ids: Vec<u64> = state.peer_selections.keys().copied().collect();
+        for id in ids {
+            render_selection(state, id, &metrics);
+        }
+        let ids: Vec<u64> = state.annotations.keys().copied().collect();
+        for id in ids {
+            render_annotation(state, id, &metrics);
+        }
+    }
+
+    if changed || state.sketch.dirty {
+        state.sketch.dirty = false;
+        let paths = state.sketch.paths(&metrics);
+        state.renderer.strokes(&paths);
+    }
+}
+
+/// Configures outbound cursor updates: at most one send per
+/// `min_interval_ms`, movements below `min_distance` are held back until the
+/// cursor has been still for `idle_ms`.
+#[wasm_bindgen]
+pub fn set_throttle(wss: &WebSocketSend, min_interval_ms: f64, min_distance: f32, idle_ms: f64) {
+    wss.state.borrow_mut().throttle.set_config(ThrottleConfig {
+        min_interval_ms: min_interval_ms.max(0.0),
+        min_distance: min_distance.max(0.0),
+        idle_ms: idle_ms.max(0.0),
+    });
+}
+
+/// Configures how remote cursors are smoothed. `delay_ms` trades latency for
+/// smoothness, `max_extrapolation_ms` bounds how far a cursor is predicted
+/// past its last known position when updates are late.
+#[wasm_bindgen]
+pub fn set_interpolation(wss: &WebSocketSend, delay_ms: f64, max_extrapolation_ms: f64) {
+    wss.state
+        .borrow_mut()
+        .interpolator
+        .set_config(InterpolationConfig {
+            delay_ms: delay_ms.max(0.0),
+            max_extrapolation_ms: max_extrapolation_ms.max(0.0),
+        });
+}
+
+thread_local! {
+    static SITE_SETTINGS: RefCell<SiteSettings> = RefCell::new(SiteSettings::default());
+    static PAGE_POLICY: RefCell<PagePolicy> = RefCell::new(PagePolicy::default());
+}
+
+fn site_settings_to_js(settings: &SiteSettings) -> JsValue {
+    let strings = |values: &[String]| -> js_sys::Array {
+        values
+            .iter()
+            .map(|value| JsValue::from(value.as_str()))
+            .collect()
+    };
+    let categories: js_sys::Array = settings
+        .allowed_categories
+        .iter()
+        .map(|category| JsValue::from(category.name()))
+        .collect();
+    let object = js_sys::Object::new();
+    for (key, value) in [
+        ("allow", strings(&settings.allow)),
+        ("deny", strings(&settings.deny)),
+        ("paused", strings(&settings.paused)),
+        ("allowedCategories", categories),
+    ] {
+        let _ = js_sys::Reflect::set(&object, &JsValue::from(key), &value);
+    }
+    object.into()
+}
+
+/// Reads settings saved from `site_settings`, missing lists are empty.
+fn site_settings_from_js(value: &JsValue) -> Option<SiteSettings> {
+    if !value.is_object() {
+        return None;
+    }
+    let strings = |key: &str| -> Vec<String> {
+        js_sys::Reflect::get(value, &JsValue::from(key))
+            .ok()
+            .and_then(|values| values.dyn_into::<js_sys::Array>().ok())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    Some(SiteSettings {
+        allow: strings("allow"),
+        deny: strings("deny"),
+        paused: strings("paused"),
+        allowed_categories: strings("allowedCategories")
+            .iter()
+            .filter_map(|name| Category::from_name(name))
+            .collect(),
+    })
+}
+
+/// Replaces where the user wants multiplayer, consulted whenever we are
+/// about to connect. Takes `{ allow, deny, paused, allowedCategories }`, the
+/// format of `site_settings`. Until called, only sensitive sites are off.
+#[wasm_bindgen]
+pub fn set_site_settings(settings: JsValue) -> Result<(), JsValue> {
+    let settings =
+        site_settings_from_js(&settings).ok_or_else(|| JsValue::from_str("not site settings"))?;
+    SITE_SETTINGS.with(|current| *current.borrow_mut() = settings);
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn site_settings() -> JsValue {
+    SITE_SETTINGS.with(|settings| site_settings_to_js(&settings.borrow()))
+}
+
+/// Pauses or resumes us on `host`, returning the new settings to save.
+#[wasm_bindgen]
+pub fn pause_site(host: &str, paused: bool) -> JsValue {
+    SITE_SETTINGS.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        settings.set_paused(host, paused);
+        site_settings_to_js(&settings)
+    })
+}
+
+/// Why we stay off on `host`: `"paused"`, `"denied"` or the sensitive
+/// category it is in. Undefined if we run there.
+#[wasm_bindgen]
+pub fn site_blocked_reason(host: &str) -> Option<String> {
+    SITE_SETTINGS.with(|settings| settings.borrow().decide(host).reason().map(String::from))
+}
+
+/// Names of the sensitive categories, for listing them in settings.
+#[wasm_bindgen]
+pub fn site_categories() -> js_sys::Array {
+    Category::ALL
+        .iter()
+        .map(|category| JsValue::from(category.name()))
+        .collect()
+}
+
+/// Name of the meta tag a page declares its policy in, see `set_page_policy`.
+#[wasm_bindgen]
+pub fn policy_meta_name() -> String {
+    String::from(POLICY_META_NAME)
+}
+
+/// Path of the file a site declares its policy in, see `set_page_policy`.
+#[wasm_bindgen]
+pub fn policy_well_known_path() -> String {
+    String::from(POLICY_WELL_KNOWN_PATH)
+}
+
+/// Applies what the site's owner declared for this page, in the content of
+/// its `policy_meta_name` meta tag and its `policy_well_known_path` file,
+/// either of which may be missing. A policy that doesn't parse keeps us off
+/// the page, its owner meant to say something.
+#[wasm_bindgen]
+pub fn set_page_policy(meta: Option<String>, well_known: Option<String>) -> Result<(), JsValue> {
+    let path = web_sys::window()
+        .and_then(|window| window.location().pathname().ok())
+        .unwrap_or_default();
+    let parsed = well_known
+        .as_deref()
+        .map(PagePolicy::from_json)
+        .transpose()
+        .and_then(|site| {
+            let page = meta.as_deref().map(PagePolicy::from_meta).transpose()?;
+            Ok(PagePolicy::resolve(
+                &site.unwrap_or_default(),
+                &page.unwrap_or_default(),
+                &path,
+            ))
+        });
+    let (policy, result) = match parsed {
+        Ok(policy) => (policy, Ok(())),
+        Err(e) => (
+            PagePolicy {
+                enabled: false,
+                ..PagePolicy::default()
+            },
+            Err(JsValue::from_str(&format!("invalid page policy: {}", e))),
+        ),
+    };
+    PAGE_POLICY.with(|current| *current.borrow_mut() = policy);
+    result
+}
+
+/// Asks to join the instance of the room peer `id` is in, when busy rooms
+/// are split up. Applies from the next time we connect, so call it right
+/// after starting, e.g. with an id from an invite link.
+#[wasm_bindgen]
+pub fn set_invited_by(wss: &WebSocketSend, id: Option<u64>) {
+    wss.state.borrow_mut().invited_by = id;
+}
+
+/// Instance of the room we are in, `0` unless it overflowed. Peers in
+/// other instances don't see us.
+#[wasm_bindgen]
+pub fn room_instance(wss: &WebSocketSend) -> Option<u32> {
+    wss.state.borrow().instance
+}
+
+/// Disconnects for good, e.g. once the user paused us on this site.
+#[wasm_bindgen]
+pub fn stop_websocket(wss: &WebSocketSend) {
+    let ws = wss.websocket.get();
+    let mut state = wss.state.borrow_mut();
+    state.stopped = true;
+    forget_peers(&ws, &mut state);
+    let _ = ws.close();
+}
+
+/// Connects and forwards remote cursor events to the given callbacks.
+#[wasm_bindgen]
+pub fn start_websocket(
     set_cursor_callback: js_sys::Function,
     spawn_cursor_callback: js_sys::Function,
     despawn_cursor_callback: js_sys::Function,
     url: js_sys::JsString,
 ) -> Result<WebSocketSend, JsValue> {
-    // Connect to an echo server
-    let ws: WebSocket = WebSocket::new("wss://multiplayer-web.fly.dev/ws/")?;
+    let renderer = CallbackRenderer {
+        set_cursor: set_cursor_callback,
+        spawn_cursor: spawn_cursor_callback,
+        despawn_cursor: despawn_cursor_callback,
+    };
+    connect(Box::new(renderer), url)
+}
+
+/// Connects and draws remote cursors on the page with the built-in renderer.
+#[wasm_bindgen]
+pub fn start_websocket_with_renderer(url: js_sys::JsString) -> Result<WebSocketSend, JsValue> {
+    connect(Box::new(DomRenderer::new()?), url)
+}
+
+fn open_socket(server_url: &str) -> Result<WebSocket, JsValue> {
+    let ws = WebSocket::new(server_url)?;
     // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
     ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+    Ok(ws)
+}
+
+fn connect(renderer: Box<dyn Renderer>, url: js_sys::JsString) -> Result<WebSocketSend, JsValue> {
+    let host = web_sys::window()
+        .and_then(|window| window.location().hostname().ok())
+        .unwrap_or_default();
+    if let Some(reason) = site_blocked_reason(&host) {
+        return Err(JsValue::from_str(&format!(
+            "multiplayer is off on {} ({})",
+            host, reason
+        )));
+    }
+    let policy = PAGE_POLICY.with(|policy| policy.borrow().clone());
+    if !policy.enabled {
+        return Err(JsValue::from_str(&format!(
+            "multiplayer is off on this page of {} (site policy)",
+            host
+        )));
+    }
+    let server_url = policy
+        .relay
+        .clone()
+        .unwrap_or_else(|| String::from(SERVER_URL));
+    let socket = Socket(Rc::new(RefCell::new(open_socket(&server_url)?)));
+
+    let user_id = rand::thread_rng().gen_range(u64::MIN..u64::MAX);
+    let metrics_stale = Rc::new(Cell::new(true));
+    let state = Rc::new(RefCell::new(ClientState {
+        user_id,
+        server_url,
+        room_config: policy.room_config(),
+        renderer,
+        peers: HashMap::new(),
+        reconnect: Reconnect::new(),
+        interpolator: Interpolator::new(InterpolationConfig::default()),
+        throttle: OutboundThrottle::new(ThrottleConfig::default()),
+        seq: 0,
+        metrics: None,
+        page_metrics: None,
+        metrics_stale: metrics_stale.clone(),
+        pointer: None,
+        anchoring: false,
+        viewport_dirty: false,
+        viewport_sent_at: f64::NEG_INFINITY,
+        peer_viewports: HashMap::new(),
+        viewport_callback: None,
+        peer_presence: HashMap::new(),
+        presence_callback: None,
+        following: None,
+        follow_scroll: None,
+        chat_callback: None,
+        typing_sent_at: f64::NEG_INFINITY,
+        chat_message_callback: None,
+        chat_history_callback: None,
+        chat_seen: None,
+        ping_callback: None,
+        reaction_callback: None,
+        selection_dirty: false,
+        selection_sent_at: f64::NEG_INFINITY,
+        sent_selection: None,
+        peer_selections: HashMap::new(),
+        selection_callback: None,
+        annotations: HashMap::new(),
+        annotation_callback: None,
+        sketch: Sketch::new(user_id),
+        stroke_sent_at: f64::NEG_INFINITY,
+        room_state: LwwMap::new(),
+        state_observers: Vec::new(),
+        owner: None,
+        muted: HashSet::new(),
+        moderation_callback: None,
+        blocked: BlockList::default(),
+        share_blocked: false,
+        block_list_callback: None,
+        stopped: false,
+        invited_by: None,
+        instance: None,
+    }));
+
+    // the page is measured again only once it scrolled or resized
+    if let Some(window) = web_sys::window() {
+        let stale = metrics_stale.clone();
+        let on_change = Closure::<dyn FnMut()>::new(move || stale.set(true));
+        for event in ["scroll", "resize"] {
+            let _ =
+                window.add_event_listener_with_callback(event, on_change.as_ref().unchecked_ref());
+        }
+        on_change.forget();
+    }
 
+    // remote cursors are moved and our own position flushed once per frame
+    let frame_state = state.clone();
+    let frame_socket = socket.clone();
+    let frame_callback: FrameCallback = Rc::new(RefCell::new(None));
+    let next_frame = frame_callback.clone();
+    *frame_callback.borrow_mut() = Some(Closure::new(move |_timestamp: f64| {
+        let now = now();
+        {
+            let frame_ws = frame_socket.get();
+            let mut state = frame_state.borrow_mut();
+            if state.stopped {
+                return; // no more frames
+            }
+            render_frame(&mut state, now);
+            state.renderer.frame(now);
+            follow_frame(&frame_ws, &mut state);
+            flush_location(&frame_ws, &mut state, now);
+            flush_viewport(&frame_ws, &mut state, now);
+            flush_selection(&frame_ws, &mut state, now);
+            flush_stroke(&frame_ws, &mut state, now);
+        }
+        if let Some(callback) = next_frame.borrow().as_ref() {
+            request_animation_frame(callback);
+        }
+    }));
+    if let Some(callback) = frame_callback.borrow().as_ref() {
+        request_animation_frame(callback);
+    }
+
+    attach(&socket, &state, String::from(&url));
+
+    Ok(WebSocketSend {
+        websocket: socket,
+        state,
+    })
+}
+
+/// Handles the events of the socket currently in `socket`, and replaces it
+/// with a new one once it closes.
+fn attach(socket: &Socket, state: &Rc<RefCell<ClientState>>, url: String) {
+    let ws = socket.get();
+    let user_id = state.borrow().user_id;
+
+    let message_state = state.clone();
+    let message_socket = socket.clone();
     let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
         // Handle difference Text/Binary,...
         if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
@@ -56,28 +1629,321 @@ pub unsafe fn start_websocket(
             match Message::deserialize(&array.to_vec()) {
                 Ok(message) => match message.message_type {
                     MessageType::UserLocation => {
-                        let location = Location::deserialize(&message.data).unwrap();
-                        let _ = set_cursor_callback.call3(
-                            &JsValue::null(),
-                            &JsValue::from(location.id),
-                            &JsValue::from(location.x),
-                            &JsValue::from(location.y),
-                        );
+                        let Some(mut location) = decode(&message, Location::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if let (Some(anchor), Some(metrics)) =
+                            (location.anchor.take(), page_metrics(&mut state))
+                        {
+                            // place the cursor over the same content in our own layout
+                            if let Some((x, y)) = anchor::resolve(&anchor, &metrics) {
+                                location.x = x;
+                                location.y = y;
+                                location.space = CoordinateSpace::Document;
+                            }
+                        }
+                        if !state.blocked.hides(location.id) {
+                            state.interpolator.push(&location, now());
+                        }
                     }
                     MessageType::Spawn => {
-                        let spawn = Spawn::deserialize(&message.data).unwrap();
-                        let _ = spawn_cursor_callback.call2(
-                            &JsValue::null(),
-                            &JsValue::from(spawn.id),
-                            &JsValue::from(spawn.icon),
-                        );
+                        let Some(spawn) = decode(&message, Spawn::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if !state.blocked.hides(spawn.id) {
+                            state.renderer.spawn(spawn.id, &spawn.icon);
+                        }
+                        state.peers.insert(spawn.id, spawn.icon);
                     }
                     MessageType::Despawn => {
-                        let despawn = Despawn::deserialize(&message.data).unwrap();
-                        let _ = despawn_cursor_callback
-                            .call1(&JsValue::null(), &JsValue::from(despawn.id));
+                        let Some(despawn) = decode(&message, Despawn::deserialize) else {
+                            return;
+                        };
+                        despawn_peer(
+                            &message_socket.get(),
+                            &mut message_state.borrow_mut(),
+                            despawn.id,
+                        );
+                    }
+                    MessageType::AuthAck => {
+                        let Some(ack) = decode(&message, AuthAck::deserialize) else {
+                            return;
+                        };
+                        console_log!(
+                            "joined instance {} of the room, {} members",
+                            ack.instance,
+                            ack.members
+                        );
+                        message_state.borrow_mut().instance = Some(ack.instance);
+                    }
+                    MessageType::GoingAway => {
+                        let Some(going_away) = decode(&message, GoingAway::deserialize) else {
+                            return;
+                        };
+                        console_log!("server going away, reconnecting");
+                        message_state
+                            .borrow_mut()
+                            .reconnect
+                            .going_away(going_away.reconnect_in as f64);
+                        let _ = message_socket.get().close();
+                    }
+                    MessageType::UserChat => {
+                        let Some(chat) = decode(&message, Chat::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if state.blocked.hides(chat.id) {
+                            return;
+                        }
+                        state.renderer.chat(chat.id, &chat.text, chat.typing);
+                        if let Some(callback) = &state.chat_callback {
+                            let _ = callback.call3(
+                                &JsValue::null(),
+                                &JsValue::from(chat.id),
+                                &JsValue::from(chat.text),
+                                &JsValue::from(chat.typing),
+                            );
+                        }
+                    }
+                    MessageType::UserChatMessage => {
+                        let Some(chat_message) = decode(&message, ChatMessage::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        state.chat_seen = Some(match state.chat_seen {
+                            Some((oldest, newest)) => (oldest, newest.max(chat_message.seq)),
+                            None => (chat_message.seq, chat_message.seq),
+                        });
+                        if state.blocked.hides(chat_message.id) {
+                            return;
+                        }
+                        if let Some(callback) = &state.chat_message_callback {
+                            let _ = callback
+                                .call1(&JsValue::null(), &chat_message_to_js(&chat_message));
+                        }
+                    }
+                    MessageType::ChatHistory => {
+                        let Some(history) = decode(&message, ChatHistory::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        let empty = history.messages.is_empty();
+                        let unseen: Vec<ChatMessage> = history
+                            .messages
+                            .into_iter()
+                            .filter(|chat_message| {
+                                state.chat_seen.is_none_or(|(oldest, newest)| {
+                                    chat_message.seq < oldest || chat_message.seq > newest
+                                })
+                            })
+                            .collect();
+                        if let (Some(first), Some(last)) = (unseen.first(), unseen.last()) {
+                            state.chat_seen = Some(match state.chat_seen {
+                                Some((oldest, newest)) => {
+                                    (oldest.min(first.seq), newest.max(last.seq))
+                                }
+                                None => (first.seq, last.seq),
+                            });
+                        } else if !empty {
+                            return; // a page we were handed before
+                        }
+                        if let Some(callback) = &state.chat_history_callback {
+                            let messages: js_sys::Array = unseen
+                                .iter()
+                                .filter(|chat_message| !state.blocked.hides(chat_message.id))
+                                .map(chat_message_to_js)
+                                .collect();
+                            let _ = callback.call2(
+                                &JsValue::null(),
+                                &messages,
+                                &JsValue::from(history.has_more),
+                            );
+                        }
+                    }
+                    MessageType::UserPing => {
+                        let Some(ping) = decode(&message, Ping::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if state.blocked.hides(ping.id) {
+                            return;
+                        }
+                        let Some(metrics) = page_metrics(&mut state) else {
+                            return;
+                        };
+                        let (x, y) = metrics.viewport_position(ping.x, ping.y, ping.space);
+                        state.renderer.ping(ping.id, x, y, ping.kind);
+                        if let Some(callback) = &state.ping_callback {
+                            let args = js_sys::Array::of4(
+                                &JsValue::from(ping.id),
+                                &JsValue::from(x),
+                                &JsValue::from(y),
+                                &JsValue::from(ping.kind.name()),
+                            );
+                            let _ = callback.apply(&JsValue::null(), &args);
+                        }
+                    }
+                    MessageType::UserReaction => {
+                        let Some(reaction) = decode(&message, Reaction::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if state.blocked.hides(reaction.id) {
+                            return;
+                        }
+                        let Some(metrics) = page_metrics(&mut state) else {
+                            return;
+                        };
+                        let (x, y) =
+                            metrics.viewport_position(reaction.x, reaction.y, reaction.space);
+                        state.renderer.reaction(reaction.id, x, y, &reaction.emoji);
+                        if let Some(callback) = &state.reaction_callback {
+                            let args = js_sys::Array::of4(
+                                &JsValue::from(reaction.id),
+                                &JsValue::from(x),
+                                &JsValue::from(y),
+                                &JsValue::from(reaction.emoji),
+                            );
+                            let _ = callback.apply(&JsValue::null(), &args);
+                        }
+                    }
+                    MessageType::UserSelection => {
+                        let Some(selection) = decode(&message, TextSelection::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if state.blocked.hides(selection.id) {
+                            return;
+                        }
+                        match selection.quote.as_ref().and_then(selection::resolve) {
+                            Some(range) => {
+                                state.peer_selections.insert(selection.id, range);
+                            }
+                            None => {
+                                state.peer_selections.remove(&selection.id);
+                            }
+                        }
+                        if let Some(metrics) = page_metrics(&mut state) {
+                            render_selection(&mut state, selection.id, &metrics);
+                        }
+                        if let Some(callback) = &state.selection_callback {
+                            let text = selection
+                                .quote
+                                .map(|quote| JsValue::from(quote.exact))
+                                .unwrap_or(JsValue::null());
+                            let _ = callback.call2(
+                                &JsValue::null(),
+                                &JsValue::from(selection.id),
+                                &text,
+                            );
+                        }
+                    }
+                    MessageType::UserAnnotation => {
+                        let Some(change) = decode(&message, AnnotationChange::deserialize) else {
+                            return;
+                        };
+                        apply_annotation_change(&mut message_state.borrow_mut(), change);
+                    }
+                    MessageType::AnnotationSync => {
+                        let Some(sync) = decode(&message, AnnotationSync::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        // a re-sync replaces whatever we had
+                        let stale: Vec<u64> = state
+                            .annotations
+                            .keys()
+                            .filter(|id| !sync.annotations.iter().any(|a| a.id == **id))
+                            .copied()
+                            .collect();
+                        for id in stale {
+                            apply_annotation_change(&mut state, AnnotationChange::Delete(id));
+                        }
+                        for annotation in sync.annotations {
+                            apply_annotation_change(
+                                &mut state,
+                                AnnotationChange::Upsert(annotation),
+                            );
+                        }
+                    }
+                    MessageType::UserStrokeStart => {
+                        let Some(start) = decode(&message, StrokeStart::deserialize) else {
+                            return;
+                        };
+                        message_state.borrow_mut().sketch.apply_start(&start);
+                    }
+                    MessageType::UserStrokeAppend => {
+                        let Some(append) = decode(&message, StrokeAppend::deserialize) else {
+                            return;
+                        };
+                        message_state.borrow_mut().sketch.apply_append(&append);
+                    }
+                    MessageType::UserStrokeEnd => {
+                        let Some(end) = decode(&message, StrokeEnd::deserialize) else {
+                            return;
+                        };
+                        message_state.borrow_mut().sketch.apply_end(&end);
+                    }
+                    MessageType::UserStrokeErase => {
+                        let Some(erase) = decode(&message, StrokeErase::deserialize) else {
+                            return;
+                        };
+                        message_state.borrow_mut().sketch.apply_erase(&erase);
+                    }
+                    MessageType::StrokeSync => {
+                        let Some(sync) = decode(&message, StrokeSync::deserialize) else {
+                            return;
+                        };
+                        message_state.borrow_mut().sketch.replace(sync.strokes);
+                    }
+                    MessageType::UserStateUpdate => {
+                        let Some(update) = decode(&message, RoomStateUpdate::deserialize) else {
+                            return;
+                        };
+                        let (changed, observers) = {
+                            let mut state = message_state.borrow_mut();
+                            let changed = state.room_state.merge(update);
+                            (changed, state.state_observers.clone())
+                        };
+                        notify_state(&observers, &changed);
                     }
-                    _ => return,
+                    MessageType::UserModeration => {
+                        let Some(moderation) = decode(&message, Moderation::deserialize) else {
+                            return;
+                        };
+                        apply_moderation(&mut message_state.borrow_mut(), &moderation);
+                    }
+                    MessageType::Presence => {
+                        let Some(presence) = decode(&message, Presence::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        if presence.id == state.user_id {
+                            // the server refused a follow, its target left
+                            state.following = presence.following;
+                            state.follow_scroll = None;
+                        } else {
+                            state.peer_presence.insert(presence.id, presence);
+                        }
+                        notify_presence(&state, &presence);
+                    }
+                    MessageType::UserViewport => {
+                        let Some(viewport) = decode(&message, Viewport::deserialize) else {
+                            return;
+                        };
+                        let mut state = message_state.borrow_mut();
+                        state.peer_viewports.insert(viewport.id, viewport);
+                        if let Some(callback) = &state.viewport_callback {
+                            let _ = callback.call2(
+                                &JsValue::null(),
+                                &JsValue::from(viewport.id),
+                                &viewport_to_js(&viewport),
+                            );
+                        }
+                    }
+                    _ => (),
                 },
                 Err(e) => {
                     console_log!("{:?} - Failed to deserialize message.", e);
@@ -99,14 +1965,16 @@ pub unsafe fn start_websocket(
     onerror_callback.forget();
 
     let ws_clone = ws.clone();
+    let open_state = state.clone();
+    let open_url = url.clone();
     let onopen_callback = Closure::<dyn FnMut()>::new(move || {
         console_log!("socket opened");
-
-        let mut rng = rand::thread_rng();
+        open_state.borrow_mut().reconnect.connected();
 
         let location = Auth {
-            id: rng.gen_range(u64::MIN..u64::MAX),
-            url: String::from(&url),
+            id: user_id,
+            url: open_url.clone(),
+            invited_by: open_state.borrow().invited_by,
         };
 
         let message = Message {
@@ -114,19 +1982,115 @@ pub unsafe fn start_websocket(
             data: location.serialize().unwrap(),
         };
 
-        if let Ok(data) = message.serialize() {
-            let res = ws_clone.send_with_u8_array(&data);
-            if let Err(e) = res {
-                console_log!("{:?}", e);
-            }
+        send_message(&ws_clone, &message);
+
+        // whatever we wrote while disconnected converges with the room
+        let snapshot = open_state.borrow().room_state.snapshot();
+        if !snapshot.entries.is_empty() {
+            let message = Message {
+                message_type: MessageType::MyStateUpdate,
+                data: snapshot.serialize().unwrap(),
+            };
+            send_message(&ws_clone, &message);
+        }
+        let state = open_state.borrow();
+        if state.room_config != RoomConfig::default() {
+            let message = Message {
+                message_type: MessageType::MyRoomConfig,
+                data: state.room_config.serialize().unwrap(),
+            };
+            send_message(&ws_clone, &message);
+        }
+
+        // the server forgets who we hide when we disconnect
+        if state.share_blocked {
+            send_block_list(&ws_clone, &state.blocked);
         }
     });
 
     ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
     onopen_callback.forget();
-    let ws_clone = ws.clone();
 
-    Ok(WebSocketSend {
-        websocket: ws_clone,
-    })
+    let close_state = state.clone();
+    let close_socket = socket.clone();
+    let onclose_callback = Closure::<dyn FnMut(_)>::new(move |e: CloseEvent| {
+        let delay = {
+            let mut state = close_state.borrow_mut();
+            // whoever is still there is spawned again once we rejoin
+            forget_peers(&close_socket.get(), &mut state);
+            if e.code() == CLOSE_TRY_AGAIN {
+                state.reconnect.refused();
+            }
+            state.reconnect.next_delay(rand::thread_rng().gen())
+        };
+        if close_state.borrow().stopped {
+            return;
+        }
+        if e.code() == CLOSE_POLICY {
+            console_log!("socket closed by the server: {}", e.reason());
+            return; // kicked, coming back would only get us kicked again
+        }
+        console_log!(
+            "socket closed ({}), reconnecting in {:.0}ms",
+            e.code(),
+            delay
+        );
+        reconnect_after(
+            close_socket.clone(),
+            close_state.clone(),
+            url.clone(),
+            delay,
+        );
+    });
+    ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+    onclose_callback.forget();
+}
+
+fn reconnect_after(socket: Socket, state: Rc<RefCell<ClientState>>, url: String, delay: f64) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let server_url = state.borrow().server_url.clone();
+    let callback = Closure::once_into_js(move || match open_socket(&server_url) {
+        Ok(ws) => {
+            *socket.0.borrow_mut() = ws;
+            attach(&socket, &state, url);
+        }
+        Err(e) => console_log!("failed to reconnect: {:?}", e),
+    });
+    let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        callback.unchecked_ref(),
+        delay as i32,
+    );
+}
+
+/// Removes everything we know about peer `id` once they left.
+fn despawn_peer(ws: &WebSocket, state: &mut ClientState, id: u64) {
+    state.peers.remove(&id);
+    state.sketch.end_all(id);
+    state.interpolator.remove(id);
+    state.renderer.despawn(id);
+    state.peer_presence.remove(&id);
+    state.peer_selections.remove(&id);
+    if state.following == Some(id) {
+        set_following(ws, state, None);
+    }
+    if state.peer_viewports.remove(&id).is_some() {
+        if let Some(callback) = &state.viewport_callback {
+            let _ = callback.call2(&JsValue::null(), &JsValue::from(id), &JsValue::null());
+        }
+    }
+}
+
+/// Despawns every peer when we lose the connection, so no cursor is left
+/// behind for someone who leaves before we are back.
+fn forget_peers(ws: &WebSocket, state: &mut ClientState) {
+    let peers: Vec<u64> = state.peers.keys().copied().collect();
+    for id in peers {
+        despawn_peer(ws, state, id);
+    }
+    // told again once we rejoin
+    state.owner = None;
+    state.muted.clear();
+    state.instance = None;
 }
@@ -0,0 +1,85 @@
+/// Delay before the first retry after the connection dropped, in ms.
+const BASE_DELAY_MS: f64 = 500.0;
+/// Longest we wait between two retries, in ms.
+const MAX_DELAY_MS: f64 = 30_000.0;
+
+/// Decides when to connect again after the socket closed: with exponential
+/// backoff, or when the server asked us to if it went away on purpose.
+pub struct Reconnect {
+    /// Retries since we were last connected.
+    attempts: u32,
+    /// Retries before the last connection, see `refused`.
+    previous: u32,
+    /// Delay the server asked for before shutting down, in ms.
+    requested: Option<f64>,
+}
+
+impl Reconnect {
+    pub fn new() -> Reconnect {
+        Reconnect {
+            attempts: 0,
+            previous: 0,
+            requested: None,
+        }
+    }
+
+    pub fn connected(&mut self) {
+        self.previous = self.attempts;
+        self.attempts = 0;
+    }
+
+    /// The server turned us away right after connecting, e.g. while our id
+    /// is still held by a socket it hasn't noticed is gone. Keeps backing off
+    /// as if we never got through.
+    pub fn refused(&mut self) {
+        self.attempts = self.attempts.max(self.previous);
+    }
+
+    /// Remembers that the server asked us to come back after `delay_ms`.
+    pub fn going_away(&mut self, delay_ms: f64) {
+        self.requested = Some(delay_ms);
+    }
+
+    /// Delay before the next attempt, in ms. `random` in `0..1` spreads
+    /// clients out so they don't all come back at the same moment.
+    pub fn next_delay(&mut self, random: f64) -> f64 {
+        if let Some(delay) = self.requested.take() {
+            return delay * (1.0 + random);
+        }
+        let ceiling = (BASE_DELAY_MS * 2f64.powi(self.attempts.min(16) as i32)).min(MAX_DELAY_MS);
+        self.attempts += 1;
+        ceiling * (0.5 + random / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_until_connected() {
+        let mut reconnect = Reconnect::new();
+        assert_eq!(reconnect.next_delay(1.0), 500.0);
+        assert_eq!(reconnect.next_delay(1.0), 1000.0);
+        assert_eq!(reconnect.next_delay(0.0), 1000.0);
+        for _ in 0..20 {
+            reconnect.next_delay(1.0);
+        }
+        assert_eq!(reconnect.next_delay(1.0), MAX_DELAY_MS);
+
+        reconnect.connected();
+        assert_eq!(reconnect.next_delay(1.0), 500.0);
+
+        reconnect.connected();
+        reconnect.refused();
+        assert_eq!(reconnect.next_delay(1.0), 1000.0);
+    }
+
+    #[test]
+    fn server_requested_delay_is_used_once() {
+        let mut reconnect = Reconnect::new();
+        reconnect.going_away(2000.0);
+        assert_eq!(reconnect.next_delay(0.5), 3000.0);
+        assert_eq!(reconnect.next_delay(1.0), 500.0);
+    }
+}
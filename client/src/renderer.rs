@@ -0,0 +1,608 @@
+use crate::drawing::StrokePath;
+use crate::selection::HighlightRect;
+use shared::{Annotation, PingKind};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::{
+    CanvasRenderingContext2d, Document, Element, HtmlCanvasElement, HtmlElement, ShadowRoot,
+    ShadowRootInit, ShadowRootMode,
+};
+
+/// Draws remote cursors. Positions are fractions of the local viewport and
+/// fall outside `0.0..=1.0` for cursors that are scrolled out of view.
+pub trait Renderer {
+    fn spawn(&mut self, id: u64, icon: &str);
+    fn move_to(&mut self, id: u64, x: f32, y: f32);
+    fn despawn(&mut self, id: u64);
+
+    /// Shows (or with empty, finished `text`, hides) a chat bubble next to a cursor.
+    fn chat(&mut self, _id: u64, _text: &str, _typing: bool) {}
+
+    /// Plays a short effect at a spot on the page, in the color of user `id`.
+    fn ping(&mut self, _id: u64, _x: f32, _y: f32, _kind: PingKind) {}
+
+    /// Floats `emoji` up from a spot on the page.
+    fn reaction(&mut self, _id: u64, _x: f32, _y: f32, _emoji: &str) {}
+
+    /// Shows (or moves) a sticky note, `None` removes it. Positions are
+    /// fractions of the local viewport, like cursors.
+    fn annotation(&mut self, _id: u64, _note: Option<(f32, f32, &Annotation)>) {}
+
+    /// Replaces the highlighted text selection of user `id`; no rects clears it.
+    fn highlight(&mut self, _id: u64, _rects: &[HighlightRect]) {}
+
+    /// Replaces the drawing over the page with `strokes`.
+    fn strokes(&mut self, _strokes: &[StrokePath]) {}
+
+    /// Called once per animation frame with the current time in ms.
+    fn frame(&mut self, _now: f64) {}
+}
+
+/// Forwards every event to JS callbacks, for pages that draw cursors themselves.
+pub struct CallbackRenderer {
+    pub set_cursor: js_sys::Function,
+    pub spawn_cursor: js_sys::Function,
+    pub despawn_cursor: js_sys::Function,
+}
+
+impl Renderer for CallbackRenderer {
+    fn spawn(&mut self, id: u64, icon: &str) {
+        let _ = self
+            .spawn_cursor
+            .call2(&JsValue::null(), &JsValue::from(id), &JsValue::from(icon));
+    }
+
+    fn move_to(&mut self, id: u64, x: f32, y: f32) {
+        let _ = self.set_cursor.call3(
+            &JsValue::null(),
+            &JsValue::from(id),
+            &JsValue::from(x),
+            &JsValue::from(y),
+        );
+    }
+
+    fn despawn(&mut self, id: u64) {
+        let _ = self
+            .despawn_cursor
+            .call1(&JsValue::null(), &JsValue::from(id));
+    }
+}
+
+const STYLE: &str = r#"
+:host {
+    all: initial;
+    position: fixed;
+    inset: 0;
+    pointer-events: none;
+    z-index: 2147483647;
+    overflow: hidden;
+}
+.cursor {
+    position: absolute;
+    top: 0;
+    left: 0;
+    will-change: transform;
+}
+.cursor svg {
+    display: block;
+    width: 16px;
+    height: 16px;
+}
+.cursor.offscreen svg,
+.cursor.offscreen .label {
+    display: none;
+}
+.cursor.offscreen::before {
+    content: "";
+    display: block;
+    width: 10px;
+    height: 10px;
+    border: 2px solid white;
+    border-radius: 50%;
+    background: currentColor;
+}
+.bubble {
+    position: absolute;
+    top: 36px;
+    left: 10px;
+    max-width: 240px;
+    padding: 4px 8px;
+    border-radius: 8px;
+    color: white;
+    font: 13px/18px system-ui, sans-serif;
+    overflow-wrap: break-word;
+}
+.bubble.typing::after {
+    content: "…";
+}
+.bubble:empty:not(.typing),
+.cursor.offscreen .bubble {
+    display: none;
+}
+.drawing {
+    position: absolute;
+    inset: 0;
+    width: 100%;
+    height: 100%;
+}
+.note {
+    position: absolute;
+    top: 0;
+    left: 0;
+    max-width: 200px;
+    padding: 6px 8px;
+    border-top: 4px solid currentColor;
+    border-radius: 2px;
+    background: #fff8b8;
+    box-shadow: 0 1px 4px rgba(0, 0, 0, 0.3);
+    font: 13px/18px system-ui, sans-serif;
+    color: #222;
+    white-space: pre-wrap;
+    overflow-wrap: break-word;
+}
+.highlight div {
+    position: absolute;
+    background: currentColor;
+    opacity: 0.25;
+    border-radius: 2px;
+}
+.effect {
+    position: absolute;
+    top: 0;
+    left: 0;
+}
+.ping {
+    position: absolute;
+    top: -20px;
+    left: -20px;
+    width: 40px;
+    height: 40px;
+}
+.ping::before,
+.ping::after {
+    content: "";
+    position: absolute;
+    inset: 0;
+    box-sizing: border-box;
+    border: 3px solid currentColor;
+    border-radius: 50%;
+    animation: ping 1s ease-out forwards;
+}
+.ping::after {
+    animation-delay: 0.25s;
+    opacity: 0;
+}
+.ping.look::before,
+.ping.look::after {
+    border-style: dashed;
+}
+.ping.warning::before,
+.ping.warning::after {
+    border-radius: 4px;
+    transform: rotate(45deg);
+    animation-name: ping-warning;
+}
+.reaction {
+    position: absolute;
+    top: -14px;
+    left: -14px;
+    font: 28px/28px system-ui, sans-serif;
+    animation: reaction 1.6s ease-out forwards;
+}
+@keyframes reaction {
+    from { transform: translateY(0) scale(0.6); opacity: 1; }
+    20% { transform: translateY(-12px) scale(1.1); opacity: 1; }
+    to { transform: translateY(-80px) scale(1); opacity: 0; }
+}
+@keyframes ping {
+    from { transform: scale(0.2); opacity: 1; }
+    to { transform: scale(1.6); opacity: 0; }
+}
+@keyframes ping-warning {
+    from { transform: rotate(45deg) scale(0.2); opacity: 1; }
+    to { transform: rotate(45deg) scale(1.4); opacity: 0; }
+}
+.label {
+    position: absolute;
+    top: 16px;
+    left: 10px;
+    padding: 1px 6px;
+    border-radius: 4px;
+    color: white;
+    font: 11px/16px system-ui, sans-serif;
+    white-space: nowrap;
+}
+"#;
+
+const ARROW: &str = r#"<svg viewBox="0 0 16 16"><path d="M1 1 L1 14 L5 10 L8 15 L10 14 L7 9 L12 9 Z" stroke="white" stroke-width="1"/></svg>"#;
+
+/// How long a chat bubble stays up after its last update, in ms.
+const CHAT_BUBBLE_MS: f64 = 5000.0;
+
+/// How long a ping effect stays in the DOM, in ms. Covers its animation.
+const PING_MS: f64 = 1300.0;
+
+/// How long a reaction stays in the DOM, in ms. Covers its animation.
+const REACTION_MS: f64 = 1700.0;
+
+/// Outer size of the dot shown at the viewport edge for off-screen cursors, in px.
+const EDGE_INDICATOR_SIZE: f64 = 14.0;
+
+/// Draws cursors with the DOM, inside a shadow root so page styles can't reach them.
+pub struct DomRenderer {
+    document: Document,
+    host: Element,
+    root: ShadowRoot,
+    cursors: HashMap<u64, HtmlElement>,
+    /// Open chat bubbles and when they expire.
+    bubbles: HashMap<u64, (HtmlElement, f64)>,
+    /// Canvas the drawing layer is painted on, created with the first stroke.
+    canvas: Option<(HtmlCanvasElement, CanvasRenderingContext2d)>,
+    /// Sticky notes by annotation id.
+    notes: HashMap<u64, HtmlElement>,
+    /// Selection highlights, one container per user.
+    highlights: HashMap<u64, HtmlElement>,
+    /// Running ping and reaction effects and when they are removed.
+    effects: Vec<(HtmlElement, f64)>,
+}
+
+impl DomRenderer {
+    pub fn new() -> Result<DomRenderer, JsValue> {
+        let document = web_sys::window()
+            .and_then(|window| window.document())
+            .ok_or_else(|| JsValue::from_str("no document"))?;
+        let host = document.create_element("multiplayer-web")?;
+        let root = host.attach_shadow(&ShadowRootInit::new(ShadowRootMode::Closed))?;
+
+        let style = document.create_element("style")?;
+        style.set_text_content(Some(STYLE));
+        root.append_child(&style)?;
+
+        let parent = document
+            .document_element()
+            .ok_or_else(|| JsValue::from_str("no document element"))?;
+        parent.append_child(&host)?;
+
+        Ok(DomRenderer {
+            document,
+            host,
+            root,
+            cursors: HashMap::new(),
+            bubbles: HashMap::new(),
+            canvas: None,
+            notes: HashMap::new(),
+            highlights: HashMap::new(),
+            effects: Vec::new(),
+        })
+    }
+
+    fn create_cursor(&self, id: u64) -> Result<HtmlElement, JsValue> {
+        let color = color_for(id);
+
+        let cursor: HtmlElement = self.document.create_element("div")?.dyn_into()?;
+        cursor.set_class_name("cursor");
+        cursor.set_inner_html(ARROW);
+        cursor.style().set_property("fill", &color)?;
+        cursor.style().set_property("color", &color)?;
+
+        let label: HtmlElement = self.document.create_element("span")?.dyn_into()?;
+        label.set_class_name("label");
+        label.set_text_content(Some(&format!("{:04x}", id & 0xffff)));
+        label.style().set_property("background", &color)?;
+        cursor.append_child(&label)?;
+
+        self.root.append_child(&cursor)?;
+        Ok(cursor)
+    }
+
+    fn create_canvas(&self) -> Result<(HtmlCanvasElement, CanvasRenderingContext2d), JsValue> {
+        let canvas: HtmlCanvasElement = self.document.create_element("canvas")?.dyn_into()?;
+        canvas.set_class_name("drawing");
+        let context: CanvasRenderingContext2d = canvas
+            .get_context("2d")?
+            .ok_or_else(|| JsValue::from_str("no 2d context"))?
+            .dyn_into()?;
+        // below everything else
+        self.root.insert_before(
+            &canvas,
+            self.root.first_element_child().as_ref().map(|e| e.as_ref()),
+        )?;
+        Ok((canvas, context))
+    }
+
+    fn create_highlight(&self, id: u64, rects: &[HighlightRect]) -> Result<HtmlElement, JsValue> {
+        let (width, height) = viewport_size();
+        let highlight: HtmlElement = self.document.create_element("div")?.dyn_into()?;
+        highlight.set_class_name("highlight");
+        highlight.style().set_property("color", &color_for(id))?;
+        for (x, y, w, h) in rects {
+            let rect: HtmlElement = self.document.create_element("div")?.dyn_into()?;
+            let style = rect.style();
+            style.set_property("left", &format!("{}px", width * *x as f64))?;
+            style.set_property("top", &format!("{}px", height * *y as f64))?;
+            style.set_property("width", &format!("{}px", width * *w as f64))?;
+            style.set_property("height", &format!("{}px", height * *h as f64))?;
+            highlight.append_child(&rect)?;
+        }
+        // below cursors, so they stay readable over a selection
+        self.root.insert_before(
+            &highlight,
+            self.root.first_element_child().as_ref().map(|e| e.as_ref()),
+        )?;
+        Ok(highlight)
+    }
+
+    /// Adds an effect element at `x`/`y` (viewport fractions), removed after `duration` ms.
+    fn add_effect(&mut self, x: f32, y: f32, duration: f64, build: impl FnOnce(&HtmlElement)) {
+        if !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y) {
+            return;
+        }
+        let (width, height) = viewport_size();
+
+        // the animation uses transform, so position the wrapper instead
+        let create = || -> Result<HtmlElement, JsValue> {
+            let wrapper: HtmlElement = self.document.create_element("div")?.dyn_into()?;
+            wrapper.set_class_name("effect");
+            let transform = format!("translate({}px, {}px)", width * x as f64, height * y as f64);
+            wrapper.style().set_property("transform", &transform)?;
+            let effect: HtmlElement = self.document.create_element("div")?.dyn_into()?;
+            build(&effect);
+            wrapper.append_child(&effect)?;
+            self.root.append_child(&wrapper)?;
+            Ok(wrapper)
+        };
+        match create() {
+            Ok(wrapper) => self.effects.push((wrapper, crate::now() + duration)),
+            Err(e) => crate::console_log!("{:?} - Failed to create effect.", e),
+        }
+    }
+}
+
+impl Renderer for DomRenderer {
+    fn spawn(&mut self, id: u64, _icon: &str) {
+        if self.cursors.contains_key(&id) {
+            return;
+        }
+        match self.create_cursor(id) {
+            Ok(cursor) => {
+                self.cursors.insert(id, cursor);
+            }
+            Err(e) => crate::console_log!("{:?} - Failed to create cursor.", e),
+        }
+    }
+
+    fn move_to(&mut self, id: u64, x: f32, y: f32) {
+        if let Some(cursor) = self.cursors.get(&id) {
+            let (width, height) = viewport_size();
+            let offscreen = !(0.0..=1.0).contains(&x) || !(0.0..=1.0).contains(&y);
+            let (x, y) = if offscreen {
+                // pin an indicator to the edge closest to the cursor
+                (
+                    (width * x as f64).clamp(0.0, (width - EDGE_INDICATOR_SIZE).max(0.0)),
+                    (height * y as f64).clamp(0.0, (height - EDGE_INDICATOR_SIZE).max(0.0)),
+                )
+            } else {
+                (width * x as f64, height * y as f64)
+            };
+            cursor.set_class_name(if offscreen {
+                "cursor offscreen"
+            } else {
+                "cursor"
+            });
+            let transform = format!("translate({}px, {}px)", x, y);
+            let _ = cursor.style().set_property("transform", &transform);
+        }
+    }
+
+    fn despawn(&mut self, id: u64) {
+        self.bubbles.remove(&id);
+        if let Some(highlight) = self.highlights.remove(&id) {
+            highlight.remove();
+        }
+        if let Some(cursor) = self.cursors.remove(&id) {
+            cursor.remove();
+        }
+    }
+
+    fn chat(&mut self, id: u64, text: &str, typing: bool) {
+        if text.is_empty() && !typing {
+            if let Some((bubble, _)) = self.bubbles.remove(&id) {
+                bubble.remove();
+            }
+            return;
+        }
+
+        let Some(cursor) = self.cursors.get(&id) else {
+            return;
+        };
+        let expires_at = crate::now() + CHAT_BUBBLE_MS;
+        let bubble = match self.bubbles.get(&id) {
+            Some((bubble, _)) => bubble.clone(),
+            None => {
+                let Ok(bubble) = create_bubble(&self.document, cursor, id) else {
+                    return;
+                };
+                bubble
+            }
+        };
+        bubble.set_text_content(Some(text));
+        bubble.set_class_name(if typing { "bubble typing" } else { "bubble" });
+        self.bubbles.insert(id, (bubble, expires_at));
+    }
+
+    fn ping(&mut self, id: u64, x: f32, y: f32, kind: PingKind) {
+        self.add_effect(x, y, PING_MS, |ping| {
+            ping.set_class_name(&format!("ping {}", kind.name()));
+            let _ = ping.style().set_property("color", &color_for(id));
+        });
+    }
+
+    fn reaction(&mut self, _id: u64, x: f32, y: f32, emoji: &str) {
+        self.add_effect(x, y, REACTION_MS, |reaction| {
+            reaction.set_class_name("reaction");
+            reaction.set_text_content(Some(emoji));
+        });
+    }
+
+    fn annotation(&mut self, id: u64, note: Option<(f32, f32, &Annotation)>) {
+        let Some((x, y, annotation)) = note else {
+            if let Some(element) = self.notes.remove(&id) {
+                element.remove();
+            }
+            return;
+        };
+
+        let element = match self.notes.get(&id) {
+            Some(element) => element.clone(),
+            None => {
+                let create = || -> Result<HtmlElement, JsValue> {
+                    let element: HtmlElement = self.document.create_element("div")?.dyn_into()?;
+                    element.set_class_name("note");
+                    self.root.append_child(&element)?;
+                    Ok(element)
+                };
+                match create() {
+                    Ok(element) => {
+                        self.notes.insert(id, element.clone());
+                        element
+                    }
+                    Err(e) => {
+                        crate::console_log!("{:?} - Failed to create note.", e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let (width, height) = viewport_size();
+        let style = element.style();
+        let _ = style.set_property("border-top-color", &color_for(annotation.author));
+        let transform = format!("translate({}px, {}px)", width * x as f64, height * y as f64);
+        let _ = style.set_property("transform", &transform);
+        if element.text_content().as_deref() != Some(annotation.text.as_str()) {
+            element.set_text_content(Some(&annotation.text));
+        }
+    }
+
+    fn highlight(&mut self, id: u64, rects: &[HighlightRect]) {
+        if let Some(highlight) = self.highlights.remove(&id) {
+            highlight.remove();
+        }
+        if rects.is_empty() {
+            return;
+        }
+        match self.create_highlight(id, rects) {
+            Ok(highlight) => {
+                self.highlights.insert(id, highlight);
+            }
+            Err(e) => crate::console_log!("{:?} - Failed to create highlight.", e),
+        }
+    }
+
+    fn strokes(&mut self, strokes: &[StrokePath]) {
+        if self.canvas.is_none() {
+            if strokes.is_empty() {
+                return;
+            }
+            match self.create_canvas() {
+                Ok(canvas) => self.canvas = Some(canvas),
+                Err(e) => {
+                    crate::console_log!("{:?} - Failed to create canvas.", e);
+                    return;
+                }
+            }
+        }
+        let Some((canvas, context)) = &self.canvas else {
+            return;
+        };
+
+        let (width, height) = viewport_size();
+        let scale = web_sys::window()
+            .map(|window| window.device_pixel_ratio())
+            .unwrap_or(1.0);
+        let (pixel_width, pixel_height) = ((width * scale) as u32, (height * scale) as u32);
+        if canvas.width() != pixel_width || canvas.height() != pixel_height {
+            canvas.set_width(pixel_width);
+            canvas.set_height(pixel_height);
+        }
+        let _ = context.set_transform(scale, 0.0, 0.0, scale, 0.0, 0.0);
+        context.clear_rect(0.0, 0.0, width, height);
+        context.set_line_cap("round");
+        context.set_line_join("round");
+
+        for stroke in strokes {
+            let Some(((x, y), rest)) = stroke.points.split_first() else {
+                continue;
+            };
+            context.set_stroke_style_str(&format!("#{:06x}", stroke.color));
+            context.set_line_width(stroke.width as f64);
+            context.begin_path();
+            context.move_to(width * *x as f64, height * *y as f64);
+            if rest.is_empty() {
+                // a dot
+                context.line_to(width * *x as f64, height * *y as f64);
+            }
+            for (x, y) in rest {
+                context.line_to(width * *x as f64, height * *y as f64);
+            }
+            context.stroke();
+        }
+    }
+
+    fn frame(&mut self, now: f64) {
+        self.effects.retain(|(effect, expires_at)| {
+            if *expires_at > now {
+                return true;
+            }
+            effect.remove();
+            false
+        });
+        self.bubbles.retain(|_, (bubble, expires_at)| {
+            if *expires_at > now {
+                return true;
+            }
+            bubble.remove();
+            false
+        });
+    }
+}
+
+fn create_bubble(
+    document: &Document,
+    cursor: &HtmlElement,
+    id: u64,
+) -> Result<HtmlElement, JsValue> {
+    let bubble: HtmlElement = document.create_element("div")?.dyn_into()?;
+    bubble.style().set_property("background", &color_for(id))?;
+    cursor.append_child(&bubble)?;
+    Ok(bubble)
+}
+
+impl Drop for DomRenderer {
+    fn drop(&mut self) {
+        self.host.remove();
+    }
+}
+
+/// Size of the window's viewport in CSS pixels.
+fn viewport_size() -> (f64, f64) {
+    let Some(window) = web_sys::window() else {
+        return (0.0, 0.0);
+    };
+    let width = window
+        .inner_width()
+        .ok()
+        .and_then(|w| w.as_f64())
+        .unwrap_or(0.0);
+    let height = window
+        .inner_height()
+        .ok()
+        .and_then(|h| h.as_f64())
+        .unwrap_or(0.0);
+    (width, height)
+}
+
+/// Stable per-user color so everyone sees the same peer in the same color.
+pub fn color_for(id: u64) -> String {
+    format!("hsl({}, 70%, 45%)", id % 360)
+}
@@ -0,0 +1,184 @@
+use crate::anchor;
+use crate::viewport::PageMetrics;
+use shared::{TextQuote, MAX_SELECTION_LENGTH, SELECTION_CONTEXT_LENGTH};
+use wasm_bindgen::JsCast;
+use web_sys::{Document, Element, Node, Range};
+
+/// `NodeFilter.SHOW_TEXT`.
+const SHOW_TEXT: u32 = 0x4;
+
+/// A highlighted area as (x, y, width, height) fractions of the viewport.
+pub type HighlightRect = (f32, f32, f32, f32);
+
+/// Describes the page's current selection, or `None` if nothing shareable is selected.
+pub fn current_quote() -> Option<TextQuote> {
+    let window = web_sys::window()?;
+    let document = window.document()?;
+    let selection = window.get_selection().ok()??;
+    if selection.range_count() == 0 || selection.is_collapsed() {
+        return None;
+    }
+    let range = selection.get_range_at(0).ok()?;
+
+    let container = range.common_ancestor_container().ok()?;
+    let element = match container.dyn_ref::<Element>() {
+        Some(element) => element.clone(),
+        None => container.parent_element()?,
+    };
+    let path = anchor::element_path(&element)?;
+
+    let exact = range_text(&range);
+    if exact.trim().is_empty() || exact.chars().count() > MAX_SELECTION_LENGTH {
+        return None;
+    }
+
+    let before = document.create_range().ok()?;
+    before.select_node_contents(&element).ok()?;
+    before
+        .set_end(&range.start_container().ok()?, range.start_offset().ok()?)
+        .ok()?;
+    let after = document.create_range().ok()?;
+    after.select_node_contents(&element).ok()?;
+    after
+        .set_start(&range.end_container().ok()?, range.end_offset().ok()?)
+        .ok()?;
+
+    Some(TextQuote {
+        path,
+        exact,
+        prefix: last_chars(&range_text(&before), SELECTION_CONTEXT_LENGTH),
+        suffix: first_chars(&range_text(&after), SELECTION_CONTEXT_LENGTH),
+    })
+}
+
+/// Finds the quoted text in this page.
+pub fn resolve(quote: &TextQuote) -> Option<Range> {
+    let document = web_sys::window()?.document()?;
+    let element = document.query_selector(&quote.path).ok()??;
+    let (start, end) = locate(&element.text_content()?, quote)?;
+
+    let range = document.create_range().ok()?;
+    let (node, offset) = text_position(&document, &element, start)?;
+    range.set_start(&node, offset).ok()?;
+    let (node, offset) = text_position(&document, &element, end)?;
+    range.set_end(&node, offset).ok()?;
+    Some(range)
+}
+
+/// The areas `range` covers on screen.
+pub fn highlight_rects(range: &Range, metrics: &PageMetrics) -> Vec<HighlightRect> {
+    let Some(rects) = range.get_client_rects() else {
+        return Vec::new();
+    };
+    (0..rects.length())
+        .filter_map(|index| rects.item(index))
+        .filter(|rect| rect.width() > 0.0 && rect.height() > 0.0)
+        .map(|rect| {
+            (
+                (rect.left() / metrics.viewport_width) as f32,
+                (rect.top() / metrics.viewport_height) as f32,
+                (rect.width() / metrics.viewport_width) as f32,
+                (rect.height() / metrics.viewport_height) as f32,
+            )
+        })
+        .collect()
+}
+
+fn range_text(range: &Range) -> String {
+    String::from(range.unchecked_ref::<js_sys::Object>().to_string())
+}
+
+/// The text node and offset within it at `offset` UTF-16 units into `root`'s text.
+fn text_position(document: &Document, root: &Element, offset: u32) -> Option<(Node, u32)> {
+    let walker = document
+        .create_tree_walker_with_what_to_show(root, SHOW_TEXT)
+        .ok()?;
+    let mut remaining = offset;
+    while let Ok(Some(node)) = walker.next_node() {
+        let length = node.node_value().unwrap_or_default().encode_utf16().count() as u32;
+        if remaining <= length {
+            return Some((node, remaining));
+        }
+        remaining -= length;
+    }
+    None
+}
+
+/// Where `quote.exact` is in `text`, as UTF-16 offsets like the DOM uses.
+/// When it occurs more than once, the occurrence whose surroundings best
+/// match the quote's prefix and suffix wins.
+fn locate(text: &str, quote: &TextQuote) -> Option<(u32, u32)> {
+    let (start, _) = text
+        .match_indices(quote.exact.as_str())
+        .map(|(start, exact)| {
+            let end = start + exact.len();
+            let score = common_suffix(&text[..start], &quote.prefix)
+                + common_prefix(&text[end..], &quote.suffix);
+            (start, score)
+        })
+        // first occurrence on ties
+        .fold(None, |best: Option<(usize, usize)>, candidate| match best {
+            Some(best) if best.1 >= candidate.1 => Some(best),
+            _ => Some(candidate),
+        })?;
+
+    let start_utf16 = text[..start].encode_utf16().count() as u32;
+    let length_utf16 = quote.exact.encode_utf16().count() as u32;
+    Some((start_utf16, start_utf16 + length_utf16))
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(a, b)| a == b).count()
+}
+
+fn common_suffix(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn first_chars(text: &str, count: usize) -> String {
+    text.chars().take(count).collect()
+}
+
+fn last_chars(text: &str, count: usize) -> String {
+    let skip = text.chars().count().saturating_sub(count);
+    text.chars().skip(skip).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(exact: &str, prefix: &str, suffix: &str) -> TextQuote {
+        TextQuote {
+            path: String::from("html"),
+            exact: String::from(exact),
+            prefix: String::from(prefix),
+            suffix: String::from(suffix),
+        }
+    }
+
+    #[test]
+    fn context_picks_the_right_occurrence() {
+        let text = "the cat sat on the mat by the door";
+
+        assert_eq!(locate(text, &quote("the", "", "")), Some((0, 3)));
+        assert_eq!(
+            locate(text, &quote("the", "sat on ", " mat")),
+            Some((15, 18))
+        );
+        assert_eq!(locate(text, &quote("the", "by ", "")), Some((26, 29)));
+        assert_eq!(locate(text, &quote("dog", "", "")), None);
+    }
+
+    #[test]
+    fn offsets_are_utf16() {
+        let text = "🎉 party time";
+
+        assert_eq!(locate(text, &quote("party", "", "")), Some((3, 8)));
+        assert_eq!(last_chars("ab🎉", 2), "b🎉");
+    }
+}
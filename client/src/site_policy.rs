@@ -0,0 +1,214 @@
+/// Kinds of sites where we stay off unless the user allows them, sharing a
+/// cursor there could give away private information.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Category {
+    Banking,
+    Email,
+    Health,
+    Government,
+}
+
+/// Sites of a category, besides what their host name gives away.
+const BANKING_HOSTS: &[&str] = &[
+    "paypal.com",
+    "chase.com",
+    "wellsfargo.com",
+    "citi.com",
+    "capitalone.com",
+    "americanexpress.com",
+    "schwab.com",
+    "fidelity.com",
+    "hsbc.com",
+    "barclays.co.uk",
+    "revolut.com",
+    "wise.com",
+];
+const EMAIL_HOSTS: &[&str] = &[
+    "outlook.live.com",
+    "outlook.office.com",
+    "outlook.office365.com",
+    "proton.me",
+    "protonmail.com",
+    "fastmail.com",
+];
+const HEALTH_HOSTS: &[&str] = &["mychart.com", "zocdoc.com", "patient.info"];
+
+impl Category {
+    pub const ALL: [Category; 4] = [
+        Category::Banking,
+        Category::Email,
+        Category::Health,
+        Category::Government,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Banking => "banking",
+            Category::Email => "email",
+            Category::Health => "health",
+            Category::Government => "government",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL
+            .into_iter()
+            .find(|category| category.name() == name)
+    }
+
+    /// The sensitive category `host` falls in, guessed from known sites and
+    /// telling words in its name.
+    pub fn of(host: &str) -> Option<Category> {
+        let labels: Vec<&str> = host.split('.').collect();
+        let known = |hosts: &[&str]| hosts.iter().any(|pattern| matches(pattern, host));
+        if labels
+            .iter()
+            .any(|label| *label == "gov" || *label == "mil")
+        {
+            Some(Category::Government)
+        } else if known(EMAIL_HOSTS)
+            || labels
+                .iter()
+                .any(|label| matches!(*label, "mail" | "webmail" | "email"))
+        {
+            Some(Category::Email)
+        } else if known(BANKING_HOSTS) || labels.iter().any(|label| label.contains("bank")) {
+            Some(Category::Banking)
+        } else if known(HEALTH_HOSTS)
+            || labels
+                .iter()
+                .any(|label| label.contains("health") || label.contains("hospital"))
+        {
+            Some(Category::Health)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether we connect on a site, and if not why.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Decision {
+    Allowed,
+    /// The user paused us on this site.
+    Paused,
+    /// The site matches a deny pattern.
+    Denied,
+    Sensitive(Category),
+}
+
+impl Decision {
+    pub fn reason(&self) -> Option<&'static str> {
+        match self {
+            Decision::Allowed => None,
+            Decision::Paused => Some("paused"),
+            Decision::Denied => Some("denied"),
+            Decision::Sensitive(category) => Some(category.name()),
+        }
+    }
+}
+
+/// Where the user wants multiplayer, set from the popup. Patterns are host
+/// names that also cover their subdomains, a leading `*.` is optional.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SiteSettings {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    /// Hosts the user paused us on, exactly.
+    pub paused: Vec<String>,
+    /// Sensitive categories the user doesn't mind, all others are denied.
+    pub allowed_categories: Vec<Category>,
+}
+
+impl SiteSettings {
+    /// Pausing beats denying beats allowing beats a sensitive category.
+    pub fn decide(&self, host: &str) -> Decision {
+        let host = normalize(host);
+        if self.paused.iter().any(|paused| normalize(paused) == host) {
+            Decision::Paused
+        } else if self.deny.iter().any(|pattern| matches(pattern, &host)) {
+            Decision::Denied
+        } else if self.allow.iter().any(|pattern| matches(pattern, &host)) {
+            Decision::Allowed
+        } else {
+            match Category::of(&host) {
+                Some(category) if !self.allowed_categories.contains(&category) => {
+                    Decision::Sensitive(category)
+                }
+                _ => Decision::Allowed,
+            }
+        }
+    }
+
+    /// Pauses or resumes us on `host`.
+    pub fn set_paused(&mut self, host: &str, paused: bool) {
+        let host = normalize(host);
+        self.paused.retain(|other| normalize(other) != host);
+        if paused {
+            self.paused.push(host);
+        }
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn matches(pattern: &str, host: &str) -> bool {
+    let pattern = normalize(pattern);
+    let pattern = pattern.strip_prefix("*.").unwrap_or(&pattern);
+    !pattern.is_empty()
+        && (host == pattern
+            || host
+                .strip_suffix(pattern)
+                .is_some_and(|rest| rest.ends_with('.')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensitive_sites_are_off_by_default() {
+        let settings = SiteSettings::default();
+        assert_eq!(settings.decide("example.com"), Decision::Allowed);
+        assert_eq!(
+            settings.decide("mail.google.com"),
+            Decision::Sensitive(Category::Email)
+        );
+        assert_eq!(
+            settings.decide("www.PayPal.com"),
+            Decision::Sensitive(Category::Banking)
+        );
+        assert_eq!(
+            settings.decide("www.irs.gov"),
+            Decision::Sensitive(Category::Government)
+        );
+        // only whole labels of known hosts count
+        assert_eq!(settings.decide("notpaypal.com"), Decision::Allowed);
+
+        let settings = SiteSettings {
+            allowed_categories: vec![Category::Email],
+            ..SiteSettings::default()
+        };
+        assert_eq!(settings.decide("mail.google.com"), Decision::Allowed);
+    }
+
+    #[test]
+    fn patterns_and_pauses_take_precedence() {
+        let mut settings = SiteSettings {
+            allow: vec![String::from("*.mybank.com")],
+            deny: vec![String::from("news.example.com")],
+            ..SiteSettings::default()
+        };
+        assert_eq!(settings.decide("online.mybank.com"), Decision::Allowed);
+        assert_eq!(settings.decide("a.news.example.com"), Decision::Denied);
+        assert_eq!(settings.decide("example.com"), Decision::Allowed);
+
+        settings.set_paused("Online.MyBank.com", true);
+        assert_eq!(settings.decide("online.mybank.com"), Decision::Paused);
+        assert_eq!(settings.decide("other.mybank.com"), Decision::Allowed);
+        settings.set_paused("online.mybank.com", false);
+        assert_eq!(settings.decide("online.mybank.com").reason(), None);
+    }
+}
@@ -0,0 +1,135 @@
+use shared::CoordinateSpace;
+
+/// Limits on how often the local cursor position is sent to the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThrottleConfig {
+    /// Minimum time between two sends, in ms.
+    pub min_interval_ms: f64,
+    /// Movements shorter than this (in the same units as positions) are held back.
+    pub min_distance: f32,
+    /// Once the cursor has been still for this long, the held back position is sent anyway.
+    pub idle_ms: f64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        ThrottleConfig {
+            min_interval_ms: 50.0,
+            min_distance: 0.001,
+            idle_ms: 100.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pending {
+    pub x: f32,
+    pub y: f32,
+    pub space: CoordinateSpace,
+    /// Local time the position was sampled.
+    pub sampled_at: f64,
+}
+
+/// Coalesces local cursor movements so only the latest position is sent, at a bounded rate.
+pub struct OutboundThrottle {
+    config: ThrottleConfig,
+    pending: Option<Pending>,
+    last_sent: Option<(f32, f32, CoordinateSpace)>,
+    last_sent_at: f64,
+}
+
+impl OutboundThrottle {
+    pub fn new(config: ThrottleConfig) -> OutboundThrottle {
+        OutboundThrottle {
+            config,
+            pending: None,
+            last_sent: None,
+            last_sent_at: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn set_config(&mut self, config: ThrottleConfig) {
+        self.config = config;
+    }
+
+    /// Replaces any position that hasn't been sent yet.
+    pub fn push(&mut self, x: f32, y: f32, space: CoordinateSpace, now: f64) {
+        self.pending = Some(Pending {
+            x,
+            y,
+            space,
+            sampled_at: now,
+        });
+    }
+
+    /// Returns the position to send now, if any.
+    pub fn poll(&mut self, now: f64) -> Option<Pending> {
+        let pending = self.pending?;
+        if now - self.last_sent_at < self.config.min_interval_ms {
+            return None;
+        }
+
+        let far_enough = match self.last_sent {
+            Some((x, y, space)) if space == pending.space => {
+                let (dx, dy) = (pending.x - x, pending.y - y);
+                (dx * dx + dy * dy).sqrt() >= self.config.min_distance
+            }
+            _ => true,
+        };
+        let settled = now - pending.sampled_at >= self.config.idle_ms;
+
+        if !far_enough && !settled {
+            return None;
+        }
+
+        self.pending = None;
+        let position = (pending.x, pending.y, pending.space);
+        if self.last_sent == Some(position) {
+            return None;
+        }
+        self.last_sent = Some(position);
+        self.last_sent_at = now;
+        Some(pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> OutboundThrottle {
+        OutboundThrottle::new(ThrottleConfig {
+            min_interval_ms: 50.0,
+            min_distance: 0.01,
+            idle_ms: 100.0,
+        })
+    }
+
+    #[test]
+    fn only_latest_position_is_sent_per_interval() {
+        let mut throttle = throttle();
+        throttle.push(0.1, 0.1, CoordinateSpace::Document, 0.0);
+        assert!(throttle.poll(0.0).is_some());
+
+        throttle.push(0.2, 0.2, CoordinateSpace::Document, 10.0);
+        throttle.push(0.3, 0.3, CoordinateSpace::Document, 20.0);
+        assert_eq!(throttle.poll(30.0), None);
+
+        let sent = throttle.poll(50.0).unwrap();
+        assert_eq!((sent.x, sent.y), (0.3, 0.3));
+    }
+
+    #[test]
+    fn small_movements_are_sent_once_still() {
+        let mut throttle = throttle();
+        throttle.push(0.1, 0.1, CoordinateSpace::Document, 0.0);
+        throttle.poll(0.0);
+
+        throttle.push(0.105, 0.1, CoordinateSpace::Document, 60.0);
+        assert_eq!(throttle.poll(70.0), None);
+
+        let sent = throttle.poll(160.0).unwrap();
+        assert_eq!((sent.x, sent.y), (0.105, 0.1));
+        assert_eq!(throttle.poll(500.0), None);
+    }
+}
@@ -0,0 +1,141 @@
+use shared::{CoordinateSpace, Viewport};
+
+/// Scroll position and sizes needed to convert between viewport and document space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageMetrics {
+    pub scroll_x: f64,
+    pub scroll_y: f64,
+    pub viewport_width: f64,
+    pub viewport_height: f64,
+    pub document_width: f64,
+    pub document_height: f64,
+}
+
+impl PageMetrics {
+    /// Reads the current metrics of the page the client runs in.
+    pub fn current() -> Option<PageMetrics> {
+        let window = web_sys::window()?;
+        let viewport_width = window.inner_width().ok()?.as_f64()?;
+        let viewport_height = window.inner_height().ok()?.as_f64()?;
+        let root = window.document()?.document_element()?;
+        Some(PageMetrics {
+            scroll_x: window.scroll_x().unwrap_or(0.0),
+            scroll_y: window.scroll_y().unwrap_or(0.0),
+            viewport_width,
+            viewport_height,
+            document_width: (root.scroll_width() as f64).max(viewport_width),
+            document_height: (root.scroll_height() as f64).max(viewport_height),
+        })
+    }
+
+    /// Converts a point in viewport pixels (e.g. `MouseEvent.clientX`) to document fractions.
+    pub fn document_position(&self, client_x: f64, client_y: f64) -> (f32, f32) {
+        (
+            ratio(client_x + self.scroll_x, self.document_width),
+            ratio(client_y + self.scroll_y, self.document_height),
+        )
+    }
+
+    /// Converts a position in `space` to fractions of this viewport. Values
+    /// outside `0.0..=1.0` are off-screen.
+    pub fn viewport_position(&self, x: f32, y: f32, space: CoordinateSpace) -> (f32, f32) {
+        match space {
+            CoordinateSpace::Viewport => (x, y),
+            CoordinateSpace::Document => (
+                ratio(
+                    x as f64 * self.document_width - self.scroll_x,
+                    self.viewport_width,
+                ),
+                ratio(
+                    y as f64 * self.document_height - self.scroll_y,
+                    self.viewport_height,
+                ),
+            ),
+        }
+    }
+
+    /// The visible region in document space, as shared with peers.
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            id: 0,
+            scroll_x: self.scroll_x as f32,
+            scroll_y: self.scroll_y as f32,
+            left: ratio(self.scroll_x, self.document_width),
+            top: ratio(self.scroll_y, self.document_height),
+            width: ratio(self.viewport_width, self.document_width),
+            height: ratio(self.viewport_height, self.document_height),
+        }
+    }
+
+    /// Scroll offset that shows the same region as a peer's `viewport`,
+    /// limited to what this page can actually scroll to.
+    pub fn scroll_to_match(&self, viewport: &Viewport) -> (f64, f64) {
+        let max_x = (self.document_width - self.viewport_width).max(0.0);
+        let max_y = (self.document_height - self.viewport_height).max(0.0);
+        (
+            (viewport.left as f64 * self.document_width).clamp(0.0, max_x),
+            (viewport.top as f64 * self.document_height).clamp(0.0, max_y),
+        )
+    }
+}
+
+fn ratio(value: f64, total: f64) -> f32 {
+    if total > 0.0 {
+        (value / total) as f32
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> PageMetrics {
+        PageMetrics {
+            scroll_x: 0.0,
+            scroll_y: 500.0,
+            viewport_width: 1000.0,
+            viewport_height: 500.0,
+            document_width: 1000.0,
+            document_height: 2000.0,
+        }
+    }
+
+    #[test]
+    fn client_points_include_scroll() {
+        assert_eq!(metrics().document_position(500.0, 250.0), (0.5, 0.375));
+    }
+
+    #[test]
+    fn document_positions_round_trip() {
+        let metrics = metrics();
+        let (x, y) = metrics.document_position(100.0, 400.0);
+        let (x, y) = metrics.viewport_position(x, y, CoordinateSpace::Document);
+
+        assert!((x - 0.1).abs() < 1e-6);
+        assert!((y - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn positions_above_the_viewport_are_negative() {
+        let (_, y) = metrics().viewport_position(0.0, 0.1, CoordinateSpace::Document);
+
+        assert!(y < 0.0);
+    }
+
+    #[test]
+    fn matching_scroll_is_clamped_to_the_page() {
+        let viewport = Viewport {
+            id: 1,
+            scroll_x: 0.0,
+            scroll_y: 1900.0,
+            left: 0.0,
+            top: 0.95,
+            width: 1.0,
+            height: 0.05,
+        };
+
+        assert_eq!(metrics().scroll_to_match(&viewport), (0.0, 1500.0));
+    }
+}
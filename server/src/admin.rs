@@ -0,0 +1,229 @@
+use crate::chat_log::fnv1a;
+use crate::config::Config;
+use crate::{policy_close, MessagingPools, Pool};
+use actix_web::http::header;
+use actix_web::web::{self, Data, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::sync::atomic::Ordering;
+use tracing::info;
+
+/// Routes of the admin API, mounted under `/admin`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/rooms", web::get().to(rooms))
+        .route("/rooms/{room}", web::get().to(room))
+        .route("/rooms/{room}", web::delete().to(close_room))
+        .route("/rooms/{room}/members/{id}", web::delete().to(kick));
+}
+
+/// How rooms are named outside the server: a hash of their url, the same
+/// one their files on disk and their logs use.
+pub fn room_id(url: &str) -> String {
+    format!("{:016x}", fnv1a(url.as_bytes()))
+}
+
+#[derive(Serialize)]
+struct RoomSummary {
+    room: String,
+    url: String,
+    members: usize,
+}
+
+#[derive(Serialize)]
+struct RoomDetail {
+    room: String,
+    url: String,
+    members: Vec<MemberDetail>,
+}
+
+/// User ids are strings, JSON numbers can't hold all of them.
+#[derive(Serialize)]
+struct MemberDetail {
+    id: String,
+    connection: u64,
+    following: Option<String>,
+    connected_seconds: u64,
+    messages: u64,
+    messages_per_second: f64,
+}
+
+/// Rooms with anyone in them.
+async fn rooms(
+    req: HttpRequest,
+    config: Data<Config>,
+    pools: Data<MessagingPools>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let pools = pools.read().unwrap();
+    let mut rooms: Vec<RoomSummary> = pools
+        .iter()
+        .map(|(url, pool)| RoomSummary {
+            room: room_id(url),
+            url: url.clone(),
+            members: pool.read().unwrap().members.len(),
+        })
+        .filter(|room| room.members > 0)
+        .collect();
+    rooms.sort_by_key(|room| Reverse(room.members));
+    HttpResponse::Ok().json(rooms)
+}
+
+async fn room(
+    req: HttpRequest,
+    config: Data<Config>,
+    pools: Data<MessagingPools>,
+    path: Path<String>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let room = path.into_inner();
+    let detail = with_room(&pools, &room, |url, pool| RoomDetail {
+        room: room.clone(),
+        url: String::from(url),
+        members: pool
+            .members
+            .iter()
+            .map(|(id, member)| {
+                let connected = member.joined_at.elapsed();
+                let messages = member.received.load(Ordering::Relaxed);
+                MemberDetail {
+                    id: id.to_string(),
+                    connection: member.connection,
+                    following: member
+                        .presence
+                        .lock()
+                        .unwrap()
+                        .following
+                        .map(|id| id.to_string()),
+                    connected_seconds: connected.as_secs(),
+                    messages,
+                    messages_per_second: messages as f64 / connected.as_secs_f64().max(1.0),
+                }
+            })
+            .collect(),
+    });
+    match detail {
+        Some(detail) => HttpResponse::Ok().json(detail),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Disconnects one member. Their client won't reconnect on its own.
+async fn kick(
+    req: HttpRequest,
+    config: Data<Config>,
+    pools: Data<MessagingPools>,
+    path: Path<(String, String)>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let (room, id) = path.into_inner();
+    let Ok(id) = id.parse::<u64>() else {
+        return HttpResponse::NotFound().finish();
+    };
+    let kicked = with_room(&pools, &room, |_, pool| match pool.members.get(&id) {
+        Some(member) => member.sender.lock().unwrap().close(policy_close("kicked")),
+        None => false,
+    });
+    if kicked == Some(true) {
+        info!(room, user = id, "kicked by admin");
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+/// Disconnects everyone in a room.
+async fn close_room(
+    req: HttpRequest,
+    config: Data<Config>,
+    pools: Data<MessagingPools>,
+    path: Path<String>,
+) -> HttpResponse {
+    if !authorized(&req, &config) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let room = path.into_inner();
+    let closed = with_room(&pools, &room, |_, pool| {
+        for member in pool.members.values() {
+            member
+                .sender
+                .lock()
+                .unwrap()
+                .close(policy_close("room closed"));
+        }
+        pool.members.len()
+    });
+    match closed {
+        Some(members) => {
+            info!(room, members, "room closed by admin");
+            HttpResponse::NoContent().finish()
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Runs `f` with the url and pool of the room named `room`.
+fn with_room<T>(pools: &MessagingPools, room: &str, f: impl FnOnce(&str, &Pool) -> T) -> Option<T> {
+    let pools = pools.read().unwrap();
+    let (url, pool) = pools.iter().find(|(url, _)| room_id(url) == room)?;
+    let pool = pool.read().unwrap();
+    Some(f(url, &pool))
+}
+
+/// Whether the request carries the admin token, or when none is configured,
+/// comes from this machine. Without a token the API is only mounted on a
+/// loopback listener, see `Config::admin_token`, this keeps it that way.
+fn authorized(req: &HttpRequest, config: &Config) -> bool {
+    let Some(token) = &config.admin_token else {
+        return req.peer_addr().is_some_and(|addr| addr.ip().is_loopback());
+    };
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+/// Compares without returning early, so timing doesn't give the token away.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn requests_need_the_token() {
+        let mut config = Config::from_env();
+        config.admin_token = Some(String::from("secret"));
+
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_http_request();
+        assert!(authorized(&request, &config));
+
+        let request = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer secreT"))
+            .to_http_request();
+        assert!(!authorized(&request, &config));
+
+        // without a token only local requests get in
+        config.admin_token = None;
+        let local = TestRequest::default()
+            .peer_addr("127.0.0.1:9000".parse().unwrap())
+            .to_http_request();
+        assert!(authorized(&local, &config));
+        let remote = TestRequest::default()
+            .peer_addr("10.0.0.2:9000".parse().unwrap())
+            .to_http_request();
+        assert!(!authorized(&remote, &config));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::chat_log::fnv1a;
+use crate::file_writer::FileWriter;
+use serde::{Deserialize, Serialize};
+use shared::{Annotation, MAX_ANCHOR_PATH};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// The annotations of a room, optionally backed by a file.
+///
+/// Rooms hold few annotations that change rarely, so the whole set is
+/// written out on every change.
+///
+/// User ids only last a session, so what an author may change is tied to
+/// where they connect from, their fingerprint. People sharing an address
+/// share their notes.
+pub struct AnnotationStore {
+    annotations: Vec<Annotation>,
+    /// Fingerprints of the authors by annotation id.
+    fingerprints: HashMap<u64, u64>,
+    limit: usize,
+    next_id: u64,
+    path: Option<PathBuf>,
+    writer: FileWriter,
+}
+
+impl AnnotationStore {
+    /// Opens the store for room `key`, loading earlier annotations from `dir`
+    /// if given. Changes are written out by `writer`.
+    pub fn open(
+        dir: Option<&Path>,
+        key: &str,
+        limit: usize,
+        writer: FileWriter,
+    ) -> AnnotationStore {
+        let path = dir.map(|dir| dir.join(format!("{:016x}.notes", fnv1a(key.as_bytes()))));
+        let mut store = AnnotationStore {
+            annotations: Vec::new(),
+            fingerprints: HashMap::new(),
+            limit,
+            next_id: 1,
+            path,
+            writer,
+        };
+
+        if let Some(path) = &store.path {
+            match read_annotations(path) {
+                Ok(stored) => {
+                    store.next_id = stored
+                        .annotations
+                        .iter()
+                        .map(|a| a.id + 1)
+                        .max()
+                        .unwrap_or(1);
+                    store.annotations = stored.annotations;
+                    store.fingerprints = stored.fingerprints;
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!(?path, error = %e, "failed to load annotations"),
+            }
+        }
+
+        store
+    }
+
+    pub fn all(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Creates `annotation` (when its id is `0`) or updates the existing one
+    /// if it was written from `fingerprint`. Returns the stored annotation,
+    /// or `None` if the change isn't allowed or the room is full.
+    pub fn upsert(
+        &mut self,
+        author: u64,
+        fingerprint: u64,
+        mut annotation: Annotation,
+    ) -> Option<Annotation> {
+        annotation.truncate();
+        if annotation
+            .anchor
+            .as_ref()
+            .is_some_and(|anchor| anchor.path.len() > MAX_ANCHOR_PATH)
+        {
+            annotation.anchor = None;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as u64)
+            .unwrap_or(0);
+
+        let stored = if annotation.id == 0 {
+            if self.annotations.len() >= self.limit {
+                return None;
+            }
+            annotation.id = self.next_id;
+            annotation.author = author;
+            annotation.created_at = now;
+            annotation.updated_at = now;
+            self.next_id += 1;
+            self.fingerprints.insert(annotation.id, fingerprint);
+            self.annotations.push(annotation.clone());
+            annotation
+        } else {
+            if !self.wrote(fingerprint, annotation.id) {
+                return None;
+            }
+            let existing = self
+                .annotations
+                .iter_mut()
+                .find(|existing| existing.id == annotation.id)?;
+            annotation.author = author;
+            annotation.created_at = existing.created_at;
+            annotation.updated_at = now;
+            *existing = annotation.clone();
+            annotation
+        };
+
+        self.persist();
+        Some(stored)
+    }
+
+    /// Removes annotation `id` if it was written from `fingerprint`.
+    pub fn delete(&mut self, fingerprint: u64, id: u64) -> bool {
+        if !self.wrote(fingerprint, id) {
+            return false;
+        }
+        self.annotations.retain(|annotation| annotation.id != id);
+        self.fingerprints.remove(&id);
+        self.persist();
+        true
+    }
+
+    /// Hands the annotations written from `fingerprint` to `author`, who
+    /// connected from there again, unless their author is still `present`
+    /// as someone else sharing the address. Returns the ones that changed
+    /// hands.
+    pub fn adopt(
+        &mut self,
+        author: u64,
+        fingerprint: u64,
+        present: impl Fn(u64) -> bool,
+    ) -> Vec<Annotation> {
+        let mut adopted = Vec::new();
+        for annotation in self.annotations.iter_mut() {
+            if annotation.author != author
+                && !present(annotation.author)
+                && self.fingerprints.get(&annotation.id) == Some(&fingerprint)
+            {
+                annotation.author = author;
+                adopted.push(annotation.clone());
+            }
+        }
+        if !adopted.is_empty() {
+            self.persist();
+        }
+        adopted
+    }
+
+    fn wrote(&self, fingerprint: u64, id: u64) -> bool {
+        self.fingerprints.get(&id) == Some(&fingerprint)
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let stored = StoredAnnotations {
+            annotations: self.annotations.clone(),
+            fingerprints: self.fingerprints.clone(),
+        };
+        match bincode::serialize(&stored) {
+            Ok(bytes) => self.writer.replace(path, bytes, "annotations"),
+            Err(e) => warn!(?path, error = %e, "failed to encode annotations"),
+        }
+    }
+}
+
+/// What the file holds.
+#[derive(Serialize, Deserialize)]
+struct StoredAnnotations {
+    annotations: Vec<Annotation>,
+    fingerprints: HashMap<u64, u64>,
+}
+
+fn read_annotations(path: &Path) -> io::Result<StoredAnnotations> {
+    let bytes = fs::read(path)?;
+    bincode::deserialize(&bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::CoordinateSpace;
+
+    fn note(id: u64, text: &str) -> Annotation {
+        Annotation {
+            id,
+            author: 0,
+            x: 0.5,
+            y: 0.5,
+            space: CoordinateSpace::Document,
+            anchor: None,
+            text: String::from(text),
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn only_authors_change_their_notes() {
+        let mut store = AnnotationStore::open(None, "example.com", 2, FileWriter::default());
+        let created = store.upsert(1, 10, note(0, "first")).unwrap();
+        assert_eq!(created.id, 1);
+        assert_eq!(created.author, 1);
+
+        // someone else, even claiming the author's id
+        assert!(store.upsert(2, 20, note(created.id, "edited")).is_none());
+        assert!(store.upsert(1, 20, note(created.id, "edited")).is_none());
+        assert!(!store.delete(20, created.id));
+        assert_eq!(
+            store
+                .upsert(1, 10, note(created.id, "edited"))
+                .unwrap()
+                .text,
+            "edited"
+        );
+
+        store.upsert(2, 20, note(0, "second")).unwrap();
+        assert!(store.upsert(2, 20, note(0, "one too many")).is_none());
+
+        // someone else behind the author's address while they are here
+        assert!(store.adopt(4, 10, |id| id == 1).is_empty());
+
+        // the author comes back with a new id
+        let adopted = store.adopt(3, 10, |_| false);
+        assert_eq!(adopted.len(), 1);
+        assert_eq!(adopted[0].author, 3);
+        assert!(store.adopt(3, 10, |_| false).is_empty());
+
+        assert!(store.delete(10, created.id));
+        assert_eq!(store.all().len(), 1);
+    }
+
+    #[test]
+    fn notes_survive_reopening() {
+        let dir = std::env::temp_dir().join(format!("annotations-test-{}", std::process::id()));
+        let writer = FileWriter::spawn();
+        {
+            let mut store = AnnotationStore::open(Some(&dir), "example.com", 10, writer.clone());
+            store.upsert(1, 10, note(0, "first")).unwrap();
+            store.upsert(1, 10, note(0, "second")).unwrap();
+            store.delete(10, 1);
+        }
+        writer.flush();
+
+        let store = AnnotationStore::open(Some(&dir), "example.com", 10, writer);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(store.all().len(), 1);
+        assert_eq!(store.all()[0].text, "second");
+        assert_eq!(store.next_id, 3);
+        assert!(store.wrote(10, 2));
+    }
+}
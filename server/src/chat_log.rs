@@ -0,0 +1,208 @@
+use crate::file_writer::FileWriter;
+use shared::{ChatHistory, ChatMessage};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// The most recent chat messages of a room, optionally backed by a file.
+///
+/// On disk the log is a sequence of length-prefixed bincode `ChatMessage`s.
+/// New messages are appended and the file is rewritten once it holds twice
+/// as many messages as are kept in memory.
+pub struct ChatLog {
+    messages: VecDeque<ChatMessage>,
+    capacity: usize,
+    next_seq: u64,
+    path: Option<PathBuf>,
+    writer: FileWriter,
+    /// Messages currently in the file, kept and dropped.
+    on_disk: usize,
+}
+
+impl ChatLog {
+    /// Opens the log for room `key`, loading earlier messages from `dir` if
+    /// given. New ones are written out by `writer`.
+    pub fn open(dir: Option<&Path>, key: &str, capacity: usize, writer: FileWriter) -> ChatLog {
+        let path = dir.map(|dir| dir.join(format!("{:016x}.chat", fnv1a(key.as_bytes()))));
+        let mut log = ChatLog {
+            messages: VecDeque::new(),
+            capacity: capacity.max(1),
+            next_seq: 1,
+            path,
+            writer,
+            on_disk: 0,
+        };
+
+        if let Some(path) = &log.path {
+            match read_messages(path) {
+                Ok(messages) => {
+                    log.on_disk = messages.len();
+                    for message in messages {
+                        log.remember(message);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!(?path, error = %e, "failed to load chat log"),
+            }
+        }
+
+        log
+    }
+
+    /// Adds a message from `user_id`, assigning its sequence number and time.
+    pub fn push(&mut self, user_id: u64, text: String) -> ChatMessage {
+        let message = ChatMessage {
+            seq: self.next_seq,
+            id: user_id,
+            text,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis() as u64)
+                .unwrap_or(0),
+        };
+        self.remember(message.clone());
+
+        if let Err(e) = self.persist(&message) {
+            warn!(path = ?self.path, error = %e, "failed to encode chat log");
+        }
+
+        message
+    }
+
+    /// Up to `limit` messages older than `before` (or the newest ones).
+    pub fn page(&self, before: Option<u64>, limit: usize) -> ChatHistory {
+        let end = match before {
+            Some(before) => self
+                .messages
+                .partition_point(|message| message.seq < before),
+            None => self.messages.len(),
+        };
+        let start = end.saturating_sub(limit);
+        ChatHistory {
+            messages: self.messages.range(start..end).cloned().collect(),
+            has_more: start > 0,
+        }
+    }
+
+    fn remember(&mut self, message: ChatMessage) {
+        self.next_seq = self.next_seq.max(message.seq + 1);
+        self.messages.push_back(message);
+        if self.messages.len() > self.capacity {
+            self.messages.pop_front();
+        }
+    }
+
+    fn persist(&mut self, message: &ChatMessage) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if self.on_disk >= self.capacity * 2 {
+            // rewrite with only what we keep
+            let mut bytes = Vec::new();
+            for message in &self.messages {
+                write_message(&mut bytes, message)?;
+            }
+            self.writer.replace(path, bytes, "chat log");
+            self.on_disk = self.messages.len();
+            return Ok(());
+        }
+
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, message)?;
+        self.writer.append(path, bytes, "chat log");
+        self.on_disk += 1;
+        Ok(())
+    }
+}
+
+fn write_message(bytes: &mut Vec<u8>, message: &ChatMessage) -> io::Result<()> {
+    let message = message
+        .serialize()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    bytes.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&message);
+    Ok(())
+}
+
+/// Records claiming to be larger than this are treated as corruption.
+const MAX_RECORD_LENGTH: usize = 64 * 1024;
+
+fn read_messages(path: &Path) -> io::Result<Vec<ChatMessage>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut messages = Vec::new();
+    let mut length = [0u8; 4];
+    loop {
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_RECORD_LENGTH {
+            break;
+        }
+        let mut bytes = vec![0u8; length];
+        if reader.read_exact(&mut bytes).is_err() {
+            break; // torn write at the end of the file
+        }
+        match ChatMessage::deserialize(&bytes) {
+            Ok(message) => messages.push(message),
+            Err(_) => break,
+        }
+    }
+    Ok(messages)
+}
+
+/// Stable hash used to name per-room files, room keys aren't safe file names.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_walk_backwards() {
+        let mut log = ChatLog::open(None, "example.com", 10, FileWriter::default());
+        for i in 0..5 {
+            log.push(1, format!("message {}", i));
+        }
+
+        let newest = log.page(None, 2);
+        assert_eq!(newest.messages.len(), 2);
+        assert_eq!(newest.messages[0].seq, 4);
+        assert!(newest.has_more);
+
+        let older = log.page(Some(4), 10);
+        assert_eq!(older.messages.len(), 3);
+        assert!(!older.has_more);
+    }
+
+    #[test]
+    fn messages_survive_reopening() {
+        let dir = std::env::temp_dir().join(format!("chat-log-test-{}", std::process::id()));
+        let writer = FileWriter::spawn();
+        {
+            let mut log = ChatLog::open(Some(&dir), "example.com", 2, writer.clone());
+            for i in 0..5 {
+                log.push(1, format!("message {}", i));
+            }
+        }
+        writer.flush();
+
+        let log = ChatLog::open(Some(&dir), "example.com", 2, writer);
+        let page = log.page(None, 10);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(page.messages.len(), 2);
+        assert_eq!(page.messages[1].text, "message 4");
+        assert_eq!(log.next_seq, 6);
+    }
+}
@@ -0,0 +1,111 @@
+use shared::{MAX_REACTION_LENGTH, MIN_ROOM_SIZE};
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Server settings, read from environment variables at startup.
+pub struct Config {
+    /// Directory chat logs are persisted to (`CHAT_HISTORY_DIR`). Chat is
+    /// kept in memory only when unset.
+    pub chat_history_dir: Option<PathBuf>,
+    /// Messages kept per room (`CHAT_HISTORY_SIZE`).
+    pub chat_history_size: usize,
+    /// Directory annotations are persisted to (`ANNOTATION_DIR`). They are
+    /// kept in memory only when unset.
+    pub annotation_dir: Option<PathBuf>,
+    /// Annotations allowed per room (`ANNOTATION_LIMIT`).
+    pub annotation_limit: usize,
+    /// Directory drawings are persisted to (`DRAWING_DIR`). They are kept in
+    /// memory only when unset.
+    pub drawing_dir: Option<PathBuf>,
+    /// Strokes allowed per room (`STROKE_LIMIT`).
+    pub stroke_limit: usize,
+    /// Directory room state is persisted to (`ROOM_STATE_DIR`). It is kept
+    /// in memory only when unset.
+    pub room_state_dir: Option<PathBuf>,
+    /// Keys allowed in a room's state (`ROOM_STATE_KEY_LIMIT`).
+    pub room_state_key_limit: usize,
+    /// Emoji users may react with (`REACTIONS`, comma separated).
+    pub reactions: Vec<String>,
+    /// How long a shutting down server waits for clients to leave before
+    /// closing their sockets (`DRAIN_TIMEOUT_MS`).
+    pub drain_timeout: Duration,
+    /// When clients are asked to reconnect after the server started
+    /// shutting down, in ms (`DRAIN_RECONNECT_MS`).
+    pub drain_reconnect: u32,
+    /// Members a room takes before newcomers go to an overflow instance of
+    /// it (`MAX_ROOM_SIZE`). A site's policy can lower it for its rooms.
+    pub max_room_size: usize,
+    /// Smallest limit a room's owner can set from their site's policy
+    /// (`MIN_ROOM_SIZE`), so the first to join can't keep everyone else out.
+    pub min_room_size: usize,
+    /// Token admin requests must carry as `Authorization: Bearer <token>`
+    /// (`ADMIN_TOKEN`). Without it, the admin API isn't served on the public
+    /// port but on `admin_port` of 127.0.0.1.
+    pub admin_token: Option<String>,
+    /// Loopback port of the admin API when there is no token (`ADMIN_PORT`).
+    pub admin_port: u16,
+    /// Whether we run behind fly's proxy (`BEHIND_PROXY`), which then tells
+    /// us where clients connect from. Anyone could send the header otherwise.
+    pub behind_proxy: bool,
+    /// Which logs to emit (`LOG_FILTER`), e.g. `info,server::drawing=debug`.
+    pub log_filter: String,
+    /// How logs are written (`LOG_FORMAT`, `human` or `json`).
+    pub log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    Human,
+    Json,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        Config {
+            chat_history_dir: env::var_os("CHAT_HISTORY_DIR").map(PathBuf::from),
+            chat_history_size: parse_env("CHAT_HISTORY_SIZE", 200),
+            annotation_dir: env::var_os("ANNOTATION_DIR").map(PathBuf::from),
+            annotation_limit: parse_env("ANNOTATION_LIMIT", 100),
+            drawing_dir: env::var_os("DRAWING_DIR").map(PathBuf::from),
+            stroke_limit: parse_env("STROKE_LIMIT", 500),
+            room_state_dir: env::var_os("ROOM_STATE_DIR").map(PathBuf::from),
+            room_state_key_limit: parse_env("ROOM_STATE_KEY_LIMIT", 500),
+            reactions: env::var("REACTIONS")
+                .map(|value| parse_list(&value))
+                .unwrap_or_else(|_| parse_list(DEFAULT_REACTIONS)),
+            drain_timeout: Duration::from_millis(parse_env("DRAIN_TIMEOUT_MS", 10_000)),
+            drain_reconnect: parse_env("DRAIN_RECONNECT_MS", 1_000),
+            max_room_size: parse_env::<usize>("MAX_ROOM_SIZE", 200).max(MIN_ROOM_SIZE as usize),
+            min_room_size: parse_env::<usize>("MIN_ROOM_SIZE", 10).max(MIN_ROOM_SIZE as usize),
+            admin_token: env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            admin_port: parse_env("ADMIN_PORT", 8081),
+            behind_proxy: parse_env("BEHIND_PROXY", false),
+            log_filter: env::var("LOG_FILTER").unwrap_or_else(|_| String::from("info")),
+            log_format: match env::var("LOG_FORMAT").as_deref() {
+                Ok("json") => LogFormat::Json,
+                _ => LogFormat::Human,
+            },
+        }
+    }
+}
+
+const DEFAULT_REACTIONS: &str = "👍,👎,❤️,😂,😮,🎉,👀,🔥";
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty() && item.len() <= MAX_REACTION_LENGTH)
+        .map(String::from)
+        .collect()
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
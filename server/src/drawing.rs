@@ -0,0 +1,216 @@
+use crate::chat_log::fnv1a;
+use crate::file_writer::FileWriter;
+use shared::{Stroke, StrokeAppend, StrokeStart, StrokeSync, MAX_STROKE_POINTS};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// The strokes drawn over a room's page, optionally backed by a file.
+///
+/// Like annotations, the whole drawing is rewritten as one `StrokeSync`
+/// whenever a stroke is finished or erased. Strokes still being drawn are
+/// only kept in memory until then.
+pub struct Drawing {
+    strokes: Vec<Stroke>,
+    /// `(author, stroke)` of strokes that are still being drawn.
+    open: HashSet<(u64, u32)>,
+    limit: usize,
+    path: Option<PathBuf>,
+    writer: FileWriter,
+}
+
+impl Drawing {
+    /// Opens the drawing for room `key`, loading earlier strokes from `dir` if
+    /// given. Changes are written out by `writer`.
+    pub fn open(dir: Option<&Path>, key: &str, limit: usize, writer: FileWriter) -> Drawing {
+        let path = dir.map(|dir| dir.join(format!("{:016x}.strokes", fnv1a(key.as_bytes()))));
+        let mut drawing = Drawing {
+            strokes: Vec::new(),
+            open: HashSet::new(),
+            limit,
+            path,
+            writer,
+        };
+
+        if let Some(path) = &drawing.path {
+            match read_strokes(path) {
+                Ok(strokes) => drawing.strokes = strokes,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!(?path, error = %e, "failed to load drawing"),
+            }
+        }
+
+        drawing
+    }
+
+    pub fn all(&self) -> &[Stroke] {
+        &self.strokes
+    }
+
+    /// Begins a stroke for `author`, unless the room is full or the stroke
+    /// exists. Color and width in `start` are brought into range.
+    pub fn start(&mut self, author: u64, start: &mut StrokeStart) -> bool {
+        if self.strokes.len() >= self.limit
+            || !start.width.is_finite()
+            || self.position(author, start.stroke).is_some()
+        {
+            return false;
+        }
+        start.color &= 0xffffff;
+        start.width = start.width.clamp(0.5, 64.0);
+        self.open.insert((author, start.stroke));
+        self.strokes.push(Stroke {
+            id: author,
+            stroke: start.stroke,
+            color: start.color,
+            width: start.width,
+            points: Vec::new(),
+        });
+        true
+    }
+
+    /// Adds points to one of `author`'s open strokes, dropping whatever goes
+    /// past `MAX_STROKE_POINTS`. Returns whether any were kept, `append` is
+    /// cut down to them.
+    pub fn append(&mut self, author: u64, append: &mut StrokeAppend) -> bool {
+        if !self.open.contains(&(author, append.stroke)) {
+            return false;
+        }
+        let Some(index) = self.position(author, append.stroke) else {
+            return false;
+        };
+        let points = &mut self.strokes[index].points;
+        append
+            .points
+            .truncate(MAX_STROKE_POINTS.saturating_sub(points.len()));
+        points.extend_from_slice(&append.points);
+        !append.points.is_empty()
+    }
+
+    /// Finishes one of `author`'s strokes.
+    pub fn end(&mut self, author: u64, stroke: u32) -> bool {
+        if !self.open.remove(&(author, stroke)) {
+            return false;
+        }
+        self.persist();
+        true
+    }
+
+    /// Finishes every stroke `author` left open, e.g. when they disconnect.
+    pub fn end_all(&mut self, author: u64) {
+        let count = self.open.len();
+        self.open.retain(|(id, _)| *id != author);
+        if self.open.len() != count {
+            self.persist();
+        }
+    }
+
+    /// Removes `author`'s latest stroke, or all of their strokes.
+    pub fn erase(&mut self, author: u64, all: bool) -> bool {
+        let removed = if all {
+            let count = self.strokes.len();
+            self.strokes.retain(|stroke| stroke.id != author);
+            self.open.retain(|(id, _)| *id != author);
+            self.strokes.len() != count
+        } else {
+            match self.strokes.iter().rposition(|stroke| stroke.id == author) {
+                Some(index) => {
+                    let stroke = self.strokes.remove(index);
+                    self.open.remove(&(author, stroke.stroke));
+                    true
+                }
+                None => false,
+            }
+        };
+        if removed {
+            self.persist();
+        }
+        removed
+    }
+
+    fn position(&self, author: u64, stroke: u32) -> Option<usize> {
+        self.strokes
+            .iter()
+            .position(|s| s.id == author && s.stroke == stroke)
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let sync = StrokeSync {
+            strokes: self.strokes.clone(),
+        };
+        match sync.serialize() {
+            Ok(bytes) => self.writer.replace(path, bytes, "drawing"),
+            Err(e) => warn!(?path, error = %e, "failed to encode drawing"),
+        }
+    }
+}
+
+fn read_strokes(path: &Path) -> io::Result<Vec<Stroke>> {
+    let bytes = fs::read(path)?;
+    StrokeSync::deserialize(&bytes)
+        .map(|sync| sync.strokes)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::StrokePoint;
+
+    fn start(stroke: u32) -> StrokeStart {
+        StrokeStart {
+            id: 0,
+            stroke,
+            color: 0xff0000,
+            width: 2.0,
+        }
+    }
+
+    fn append(stroke: u32, count: usize) -> StrokeAppend {
+        StrokeAppend {
+            id: 0,
+            stroke,
+            points: vec![StrokePoint::new(0.5, 0.5); count],
+        }
+    }
+
+    #[test]
+    fn strokes_are_limited() {
+        let mut drawing = Drawing::open(None, "example.com", 2, FileWriter::default());
+        assert!(drawing.start(1, &mut start(1)));
+        assert!(!drawing.start(1, &mut start(1)));
+        assert!(drawing.start(2, &mut start(1)));
+        assert!(!drawing.start(1, &mut start(2)));
+
+        let mut long = append(1, MAX_STROKE_POINTS + 10);
+        assert!(drawing.append(1, &mut long));
+        assert_eq!(long.points.len(), MAX_STROKE_POINTS);
+        assert!(!drawing.append(1, &mut append(1, 1)));
+
+        assert!(drawing.end(1, 1));
+        assert!(!drawing.append(1, &mut append(1, 1)));
+        assert!(!drawing.append(3, &mut append(1, 1)));
+    }
+
+    #[test]
+    fn erasing_only_touches_the_author() {
+        let mut drawing = Drawing::open(None, "example.com", 10, FileWriter::default());
+        drawing.start(1, &mut start(1));
+        drawing.start(1, &mut start(2));
+        drawing.start(2, &mut start(1));
+
+        assert!(drawing.erase(1, false));
+        assert_eq!(drawing.all().len(), 2);
+        assert_eq!(drawing.all()[0].stroke, 1);
+
+        assert!(drawing.erase(1, true));
+        assert!(!drawing.erase(1, true));
+        assert_eq!(drawing.all().len(), 1);
+        assert_eq!(drawing.all()[0].id, 2);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tracing::warn;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Writes the files of room stores on a thread of its own, so connections
+/// never wait on the disk while holding a room. Writes happen in the order
+/// they were queued, so a later snapshot of a file always wins.
+#[derive(Clone, Default)]
+pub struct FileWriter {
+    /// Where writes are queued, without it they happen on the caller's thread.
+    jobs: Option<Sender<Job>>,
+}
+
+impl FileWriter {
+    /// Starts the writing thread.
+    pub fn spawn() -> FileWriter {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name(String::from("file-writer"))
+            .spawn(move || {
+                for job in queue {
+                    job();
+                }
+            })
+            .expect("failed to start the file writer");
+        FileWriter { jobs: Some(jobs) }
+    }
+
+    /// Replaces the file at `path` with `bytes`. They are written next to it
+    /// and swapped in, so a crash can't leave half a file.
+    pub fn replace(&self, path: &Path, bytes: Vec<u8>, what: &'static str) {
+        let path = path.to_path_buf();
+        self.run(move || {
+            if let Err(e) = replace(&path, &bytes) {
+                warn!(?path, error = %e, "failed to write {}", what);
+            }
+        });
+    }
+
+    /// Adds `bytes` at the end of the file at `path`.
+    pub fn append(&self, path: &Path, bytes: Vec<u8>, what: &'static str) {
+        let path = path.to_path_buf();
+        self.run(move || {
+            if let Err(e) = append(&path, &bytes) {
+                warn!(?path, error = %e, "failed to write {}", what);
+            }
+        });
+    }
+
+    /// Waits for every write queued so far, e.g. before the server exits.
+    pub fn flush(&self) {
+        let (done, finished) = mpsc::channel();
+        self.run(move || {
+            let _ = done.send(());
+        });
+        let _ = finished.recv();
+    }
+
+    fn run(&self, job: impl FnOnce() + Send + 'static) {
+        match &self.jobs {
+            Some(jobs) => {
+                if let Err(mpsc::SendError(job)) = jobs.send(Box::new(job)) {
+                    job(); // the thread is gone, better late than never
+                }
+            }
+            None => job(),
+        }
+    }
+}
+
+fn replace(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut written = path.as_os_str().to_owned();
+    written.push(".tmp");
+    fs::write(&written, bytes)?;
+    fs::rename(&written, path)
+}
+
+fn append(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(bytes)
+}
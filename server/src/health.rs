@@ -0,0 +1,130 @@
+use crate::MessagingPools;
+use actix_web::{rt::time::sleep, web::Data, HttpResponse};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Times the room registry is tried before readiness gives up on it.
+const REGISTRY_ATTEMPTS: u32 = 10;
+/// Wait between tries of the room registry.
+const REGISTRY_RETRY: Duration = Duration::from_millis(10);
+
+/// What `/healthz` and `/readyz` report on.
+pub struct Health {
+    started: Instant,
+    /// Set once the server stops taking new connections.
+    draining: AtomicBool,
+    /// Set once the sockets still open are to be closed.
+    closing: AtomicBool,
+}
+
+impl Health {
+    pub fn new() -> Health {
+        Health {
+            started: Instant::now(),
+            draining: AtomicBool::new(false),
+            closing: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Stops taking new connections, connected clients are told to go away.
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::Relaxed)
+    }
+
+    /// Has every connection close its socket.
+    pub fn close(&self) {
+        self.drain();
+        self.closing.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize)]
+struct Status {
+    status: &'static str,
+    version: &'static str,
+    uptime_seconds: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    draining: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registry: Option<bool>,
+}
+
+impl Status {
+    fn new(health: &Health, status: &'static str) -> Status {
+        Status {
+            status,
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_seconds: health.started.elapsed().as_secs(),
+            draining: None,
+            registry: None,
+        }
+    }
+}
+
+/// The process is up and serving HTTP.
+pub async fn healthz(health: Data<Health>) -> HttpResponse {
+    HttpResponse::Ok().json(Status::new(&health, "ok"))
+}
+
+/// New connections are welcome: we aren't draining and the room registry
+/// can be locked in time.
+pub async fn readyz(health: Data<Health>, pools: Data<MessagingPools>) -> HttpResponse {
+    let draining = health.is_draining();
+    let registry = registry_responsive(&pools).await;
+    let ready = !draining && registry;
+
+    let mut status = Status::new(&health, if ready { "ready" } else { "unavailable" });
+    status.draining = Some(draining);
+    status.registry = Some(registry);
+    if ready {
+        HttpResponse::Ok().json(status)
+    } else {
+        HttpResponse::ServiceUnavailable().json(status)
+    }
+}
+
+/// Whether the registry can be read without waiting on a long held (or
+/// poisoned) lock.
+async fn registry_responsive(pools: &MessagingPools) -> bool {
+    for _ in 0..REGISTRY_ATTEMPTS {
+        if pools.try_read().is_ok() {
+            return true;
+        }
+        sleep(REGISTRY_RETRY).await;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+
+    #[actix_web::test]
+    async fn not_ready_with_a_poisoned_registry() {
+        let health = Data::new(Health::new());
+        let pools: Data<MessagingPools> = Data::new(RwLock::new(HashMap::new()));
+
+        let response = readyz(health.clone(), pools.clone()).await;
+        assert_eq!(response.status(), 200);
+
+        let poisoned = pools.clone();
+        let _ = std::thread::spawn(move || {
+            let _locked = poisoned.write().unwrap();
+            panic!("poisoning the registry");
+        })
+        .join();
+        let response = readyz(health, pools).await;
+        assert_eq!(response.status(), 503);
+    }
+}
@@ -0,0 +1,91 @@
+/// Key of instance `instance` of the room at `url` in the pools. The first
+/// instance is the room itself, overflow instances are rooms of their own,
+/// with their own chat, annotations and drawing.
+pub fn instance_key(url: &str, instance: u32) -> String {
+    if instance == 0 {
+        String::from(url)
+    } else {
+        format!("{}#{}", url, instance)
+    }
+}
+
+/// What placing a user needs to know about one instance of their room.
+pub struct InstanceLoad {
+    pub instance: u32,
+    pub members: usize,
+    /// Members the instance takes, see `RoomConfig`.
+    pub capacity: usize,
+    /// The user is in it already, from a connection about to be replaced.
+    pub has_user: bool,
+    /// The member who invited the user is in it.
+    pub has_inviter: bool,
+    /// Its owner banned the user.
+    pub banned: bool,
+}
+
+impl InstanceLoad {
+    fn has_room(&self) -> bool {
+        self.members < self.capacity && !self.banned
+    }
+}
+
+/// Picks the instance a user joins: the one they are in already, their
+/// inviter's if it has room, the first one with room or else a new one.
+/// Instances the user is banned from are skipped. `instances` are in order,
+/// without gaps.
+pub fn place(instances: &[InstanceLoad]) -> u32 {
+    instances
+        .iter()
+        .find(|load| load.has_user && !load.banned)
+        .or_else(|| {
+            instances
+                .iter()
+                .find(|load| load.has_inviter && load.has_room())
+        })
+        .or_else(|| instances.iter().find(|load| load.has_room()))
+        .map(|load| load.instance)
+        .unwrap_or(instances.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(instance: u32, members: usize) -> InstanceLoad {
+        InstanceLoad {
+            instance,
+            members,
+            capacity: 2,
+            has_user: false,
+            has_inviter: false,
+            banned: false,
+        }
+    }
+
+    #[test]
+    fn fills_instances_in_order_keeping_friends_together() {
+        assert_eq!(place(&[]), 0);
+        assert_eq!(place(&[load(0, 1)]), 0);
+        assert_eq!(place(&[load(0, 2)]), 1);
+        assert_eq!(place(&[load(0, 2), load(1, 0)]), 1);
+
+        let mut instances = vec![load(0, 1), load(1, 1)];
+        instances[1].has_inviter = true;
+        assert_eq!(place(&instances), 1);
+        instances[1].members = 2;
+        assert_eq!(place(&instances), 0);
+
+        // reconnecting users keep their place even in a full instance
+        instances[1].has_user = true;
+        assert_eq!(place(&instances), 1);
+
+        // a ban keeps the user out of that instance only
+        instances[1].banned = true;
+        assert_eq!(place(&instances), 0);
+        instances[0].banned = true;
+        assert_eq!(place(&instances), 2);
+
+        assert_eq!(instance_key("https://a.com/", 0), "https://a.com/");
+        assert_eq!(instance_key("https://a.com/", 2), "https://a.com/#2");
+    }
+}
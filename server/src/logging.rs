@@ -0,0 +1,21 @@
+use crate::config::{Config, LogFormat};
+use tracing_subscriber::EnvFilter;
+
+/// Sets up the global logger from `config`, falling back to `info` if the
+/// filter doesn't parse.
+pub fn init(config: &Config) {
+    let (filter, invalid) = match EnvFilter::try_new(&config.log_filter) {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::new("info"), Some(e)),
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.log_format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+
+    if let Some(e) = invalid {
+        tracing::warn!(filter = %config.log_filter, error = %e, "invalid LOG_FILTER, using info");
+    }
+}
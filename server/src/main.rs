@@ -1,17 +1,46 @@
-use actix::{Actor, AsyncContext, StreamHandler};
+mod admin;
+mod annotations;
+mod chat_log;
+mod config;
+mod drawing;
+mod file_writer;
+mod health;
+mod instances;
+mod logging;
+mod metrics;
+mod moderation;
+mod rate_limit;
+mod room_state;
+mod shutdown;
+
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
 use actix_web::{
     web::{self, Data},
     App, Error, HttpRequest, HttpResponse, HttpServer,
 };
-use actix_web_actors::ws;
+use actix_web_actors::ws::{self, CloseCode, CloseReason};
+use annotations::AnnotationStore;
+use chat_log::{fnv1a, ChatLog};
+use config::Config;
+use drawing::Drawing;
+use file_writer::FileWriter;
+use health::Health;
+use instances::{instance_key, InstanceLoad};
+use metrics::Metrics;
+use moderation::{Owner, RoomModeration, MAX_BAN};
+use rate_limit::RateLimiter;
+use room_state::RoomState;
 use shared::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::sync::RwLock;
 use std::sync::{mpsc, Mutex};
 use std::{cell::RefCell, collections::HashMap};
 use std::{
     sync::mpsc::{Receiver, Sender},
-    time::Duration,
+    time::{Duration, Instant},
 };
+use tracing::{debug, field, info, info_span, trace, Span};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -23,179 +52,936 @@ struct MyWs {
     user_id: RefCell<Option<u64>>,
     url: RefCell<Option<String>>,
     messaging_pools: Data<MessagingPools>,
+    config: Data<Config>,
+    metrics: Data<Metrics>,
+    health: Data<Health>,
+    writer: Data<FileWriter>,
+    /// Tells this connection apart from an earlier one of the same user.
+    connection: u64,
+    /// Set once we asked the client to reconnect elsewhere.
+    going_away: RefCell<bool>,
+    /// Messages received, shared with our `Member` once we joined.
+    received: Arc<AtomicU64>,
+    /// When the client last sent anything, pongs included.
+    last_heard: RefCell<Instant>,
+    /// Where we connect from, see `fingerprint`.
+    fingerprint: u64,
+    /// Set while the room's owner has us muted, shared with our `Member`.
+    muted: Arc<AtomicBool>,
+    /// Carries the connection id, and once authed the room and user, on
+    /// everything logged for this connection.
+    span: Span,
+    chat_limiter: RefCell<RateLimiter>,
+    ping_limiter: RefCell<RateLimiter>,
+    reaction_limiter: RefCell<RateLimiter>,
+    selection_limiter: RefCell<RateLimiter>,
+    annotation_limiter: RefCell<RateLimiter>,
+    draw_limiter: RefCell<RateLimiter>,
+    state_limiter: RefCell<RateLimiter>,
 }
 
+/// Chat messages (including typing updates) a user may burst.
+const CHAT_BURST: u32 = 10;
+/// Chat messages per second a user may send once the burst is used up.
+const CHAT_PER_SECOND: f64 = 4.0;
+/// Pings a user may burst.
+const PING_BURST: u32 = 5;
+/// Pings per second a user may send once the burst is used up.
+const PING_PER_SECOND: f64 = 1.0;
+/// Reactions a user may burst.
+const REACTION_BURST: u32 = 8;
+/// Reactions per second a user may send once the burst is used up.
+const REACTION_PER_SECOND: f64 = 2.0;
+/// Selection changes a user may burst.
+const SELECTION_BURST: u32 = 10;
+/// Selection changes per second a user may send once the burst is used up.
+const SELECTION_PER_SECOND: f64 = 5.0;
+/// Annotation changes a user may burst.
+const ANNOTATION_BURST: u32 = 5;
+/// Annotation changes per second a user may send once the burst is used up.
+const ANNOTATION_PER_SECOND: f64 = 1.0;
+/// Drawing messages a user may burst.
+const DRAW_BURST: u32 = 60;
+/// Drawing messages per second a user may send once the burst is used up.
+const DRAW_PER_SECOND: f64 = 30.0;
+/// Room state updates a user may burst.
+const STATE_BURST: u32 = 20;
+/// Room state updates per second a user may send once the burst is used up.
+const STATE_PER_SECOND: f64 = 10.0;
+
+/// How often a connection checks whether the server is shutting down.
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+/// How often we ping a client, browsers answer on their own.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Silence after which a client counts as gone, e.g. its network changed
+/// without the socket closing. It leaves the room and frees its id.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
 impl Actor for MyWs {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.metrics.connections.inc();
+        info!(parent: &self.span, "connection opened");
+
+        ctx.run_interval(DRAIN_POLL, |act, ctx| {
+            let span = act.span.clone();
+            let _entered = span.enter();
+            if act.health.is_closing() {
+                info!("closing for shutdown");
+                act.leave();
+                ctx.close(Some(CloseReason {
+                    code: CloseCode::Restart,
+                    description: Some(String::from("server shutting down")),
+                }));
+                ctx.stop();
+            } else if act.health.is_draining() && !act.going_away.replace(true) {
+                let going_away = GoingAway {
+                    reconnect_in: act.config.drain_reconnect,
+                };
+                act.reply(ctx, PoolMessage::GoingAwayNotice(going_away));
+            }
+        });
+
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if act.last_heard.borrow().elapsed() > HEARTBEAT_TIMEOUT {
+                info!(parent: &act.span, "client went silent");
+                ctx.stop(); // `stopped` leaves the room
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        // sockets that drop without a close frame leave the room here
+        self.leave();
+        self.metrics.connections.dec();
+        info!("connection closed");
+    }
 }
 
-/// Handler for ws::Message message
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        match msg {
-            Ok(ws::Message::Binary(bin)) => {
-                let message_res = Message::deserialize(&bin);
-                match message_res {
-                    Ok(message) => {
-                        match message.message_type {
-                            MessageType::Auth => {
-                                // register url and set user_id
-                                match Auth::deserialize(&message.data) {
-                                    Ok(auth) => {
-                                        if self.user_id.borrow().is_some()
-                                            || self.url.borrow().is_some()
-                                        {
-                                            return; // we are already authed, do nothing
-                                        }
-
-                                        *self.user_id.borrow_mut() = Some(auth.id);
-                                        *self.url.borrow_mut() = Some(auth.url.clone());
-
-                                        let mut pools = self.messaging_pools.write().unwrap();
-
-                                        if pools.get(&auth.url).is_none() {
-                                            // construct pool for url
-                                            pools.insert(
-                                                auth.url.clone(),
-                                                RwLock::new(HashMap::new()),
-                                            );
-                                        }
-
-                                        let mut pool =
-                                            pools.get(&auth.url).unwrap().write().unwrap();
-                                        let (tx, rx): (Sender<PoolMessage>, Receiver<PoolMessage>) =
-                                            mpsc::channel();
-
-                                        // startup an interval
-                                        ctx.run_interval(
-                                            Duration::from_millis(10),
-                                            move |_, ctx| {
-                                                for pool_message in rx.try_iter() {
-                                                    match pool_message {
-                                                        PoolMessage::LocationUpdate(location) => {
-                                                            let data =
-                                                                location.serialize().unwrap();
-                                                            let message = Message {
-                                                                message_type:
-                                                                    MessageType::UserLocation,
-                                                                data,
-                                                            };
-                                                            ctx.binary(
-                                                                message.serialize().unwrap(),
-                                                            );
-                                                        }
-                                                        PoolMessage::SpawnEntity(spawn) => {
-                                                            let data = spawn.serialize().unwrap();
-                                                            let message = Message {
-                                                                message_type: MessageType::Spawn,
-                                                                data,
-                                                            };
-                                                            ctx.binary(
-                                                                message.serialize().unwrap(),
-                                                            );
-                                                        }
-                                                        PoolMessage::DespawnEntity(despawn) => {
-                                                            let data = despawn.serialize().unwrap();
-                                                            let message = Message {
-                                                                message_type: MessageType::Despawn,
-                                                                data,
-                                                            };
-                                                            ctx.binary(
-                                                                message.serialize().unwrap(),
-                                                            );
-                                                        }
-                                                    }
-                                                }
-                                            },
-                                        );
-
-                                        // insert the sender into the pool
-                                        // other thread/sockets will be sending through this
-                                        pool.insert(auth.id, Mutex::new(tx.clone()));
-
-                                        for (id, sender) in pool.iter() {
-                                            if *id == auth.id {
-                                                continue;
-                                            }
-                                            let s_lock = sender.lock().unwrap();
-                                            let _ = s_lock.send(PoolMessage::SpawnEntity(Spawn {
-                                                id: auth.id,
-                                                icon: auth.url.clone(),
-                                            }));
-                                        }
-
-                                        for (id, _) in pool.iter() {
-                                            if *id == auth.id {
-                                                continue;
-                                            }
-                                            let _ =
-                                                tx.clone().send(PoolMessage::SpawnEntity(Spawn {
-                                                    id: *id,
-                                                    icon: String::from("test"),
-                                                }));
-                                        }
-                                    }
-                                    Err(_) => {
-                                        return; // do nothing
-                                    }
-                                }
-                                return;
-                            }
-                            MessageType::UserLocation => {
-                                return; // server doesn't handle these events incoming
-                            }
-                            MessageType::Spawn => {
-                                return; // server doesn't handle these events incoming
-                            }
-                            MessageType::Despawn => {
-                                return; // server doesn't handle these events incoming
-                            }
-                            MessageType::MyLocation => match Location::deserialize(&message.data) {
-                                Ok(mut location) => {
-                                    if let (Some(url), Some(user_id)) =
-                                        (self.url.borrow().as_ref(), self.user_id.borrow().as_ref())
-                                    {
-                                        location.id = *user_id;
-                                        let pools = self.messaging_pools.read().unwrap();
-                                        let pool = pools.get(url).unwrap().read().unwrap();
-                                        for (_, sender) in pool.iter() {
-                                            let s_lock = sender.lock().unwrap();
-                                            let _ =
-                                                s_lock.send(PoolMessage::LocationUpdate(location));
-                                        }
-                                    }
-                                }
-                                Err(_) => return,
-                            },
+impl MyWs {
+    fn handle_message(&self, message: Message, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.muted.load(Ordering::Relaxed)
+            && !matches!(
+                message.message_type,
+                MessageType::Auth
+                    | MessageType::ChatHistoryRequest
+                    | MessageType::MyModeration
+                    | MessageType::MyBlockList
+                    | MessageType::MyRoomConfig
+            )
+        {
+            return; // nothing a muted member sends is relayed or kept
+        }
+
+        match message.message_type {
+            MessageType::Auth => {
+                // register url and set user_id
+                if let Ok(auth) = Auth::deserialize(&message.data) {
+                    self.auth(auth, ctx);
+                }
+            }
+            MessageType::UserLocation
+            | MessageType::Spawn
+            | MessageType::Despawn
+            | MessageType::UserViewport
+            | MessageType::Presence
+            | MessageType::UserChat
+            | MessageType::UserChatMessage
+            | MessageType::ChatHistory
+            | MessageType::UserPing
+            | MessageType::UserReaction
+            | MessageType::UserSelection
+            | MessageType::UserAnnotation
+            | MessageType::AnnotationSync
+            | MessageType::UserStrokeStart
+            | MessageType::UserStrokeAppend
+            | MessageType::UserStrokeEnd
+            | MessageType::UserStrokeErase
+            | MessageType::StrokeSync
+            | MessageType::UserStateUpdate
+            | MessageType::GoingAway
+            | MessageType::UserModeration
+            | MessageType::AuthAck => {
+                // server doesn't handle these events incoming
+            }
+            MessageType::MyLocation => {
+                if let Ok(mut location) = Location::deserialize(&message.data) {
+                    if let Some(user_id) = *self.user_id.borrow() {
+                        location.id = user_id;
+                        if location
+                            .anchor
+                            .as_ref()
+                            .is_some_and(|anchor| anchor.path.len() > MAX_ANCHOR_PATH)
+                        {
+                            location.anchor = None;
                         }
+                        self.broadcast(PoolMessage::LocationUpdate(location));
                     }
-                    Err(_) => {
-                        return; // do nothing
+                }
+            }
+            MessageType::MyViewport => {
+                if let Ok(mut viewport) = Viewport::deserialize(&message.data) {
+                    if let Some(user_id) = *self.user_id.borrow() {
+                        viewport.id = user_id;
+                        self.with_member(|member| {
+                            *member.viewport.lock().unwrap() = Some(viewport);
+                        });
+                        self.broadcast(PoolMessage::ViewportUpdate(viewport));
                     }
                 }
             }
-            Ok(ws::Message::Close(rsn)) => {
-                println!("closing: {:?}", rsn);
+            MessageType::Follow => {
+                if let Ok(follow) = Follow::deserialize(&message.data) {
+                    self.follow(follow, ctx);
+                }
+            }
+            MessageType::MyChat => {
+                if let Ok(mut chat) = Chat::deserialize(&message.data) {
+                    if let Some(user_id) = *self.user_id.borrow() {
+                        if !self.chat_limiter.borrow_mut().allow() {
+                            return; // too chatty, drop it
+                        }
+                        chat.id = user_id;
+                        chat.truncate();
+                        self.broadcast(PoolMessage::ChatUpdate(chat));
+                    }
+                }
+            }
+            MessageType::MyPing => {
+                if let Ok(mut ping) = Ping::deserialize(&message.data) {
+                    if let Some(user_id) = *self.user_id.borrow() {
+                        if !self.ping_limiter.borrow_mut().allow() {
+                            return;
+                        }
+                        ping.id = user_id;
+                        self.broadcast(PoolMessage::PingEffect(ping));
+                    }
+                }
+            }
+            MessageType::MyReaction => {
+                if let Ok(mut reaction) = Reaction::deserialize(&message.data) {
+                    if let Some(user_id) = *self.user_id.borrow() {
+                        if !self.config.reactions.contains(&reaction.emoji)
+                            || !self.reaction_limiter.borrow_mut().allow()
+                        {
+                            return;
+                        }
+                        reaction.id = user_id;
+                        self.broadcast(PoolMessage::ReactionEffect(reaction));
+                    }
+                }
+            }
+            MessageType::MySelection => {
+                if let Ok(mut selection) = TextSelection::deserialize(&message.data) {
+                    if let Some(user_id) = *self.user_id.borrow() {
+                        if selection.quote.as_ref().is_some_and(|quote| !quote.fits())
+                            || !self.selection_limiter.borrow_mut().allow()
+                        {
+                            return;
+                        }
+                        selection.id = user_id;
+                        self.with_member(|member| {
+                            *member.selection.lock().unwrap() =
+                                selection.quote.is_some().then(|| selection.clone());
+                        });
+                        self.broadcast(PoolMessage::SelectionUpdate(selection));
+                    }
+                }
+            }
+            MessageType::MyAnnotation => {
+                if let Ok(change) = AnnotationChange::deserialize(&message.data) {
+                    let Some(user_id) = *self.user_id.borrow() else {
+                        return;
+                    };
+                    if !self.annotation_limiter.borrow_mut().allow() {
+                        return;
+                    }
+                    let mut applied = None;
+                    self.with_pool(|pool| {
+                        let mut annotations = pool.annotations.lock().unwrap();
+                        applied = match change {
+                            AnnotationChange::Upsert(annotation) => annotations
+                                .upsert(user_id, self.fingerprint, annotation)
+                                .map(AnnotationChange::Upsert),
+                            AnnotationChange::Delete(id) => annotations
+                                .delete(self.fingerprint, id)
+                                .then_some(AnnotationChange::Delete(id)),
+                        };
+                    });
+                    if let Some(applied) = applied {
+                        // the author learns the id and timestamps of a new annotation
+                        self.reply(ctx, PoolMessage::AnnotationUpdate(applied.clone()));
+                        self.broadcast(PoolMessage::AnnotationUpdate(applied));
+                    }
+                }
+            }
+            MessageType::MyStrokeStart => {
+                if let Ok(mut start) = StrokeStart::deserialize(&message.data) {
+                    if let Some(user_id) =
+                        self.draw(|drawing, user_id| drawing.start(user_id, &mut start))
+                    {
+                        start.id = user_id;
+                        self.broadcast(PoolMessage::StrokeStarted(start));
+                    }
+                }
+            }
+            MessageType::MyStrokeAppend => {
+                if let Ok(mut append) = StrokeAppend::deserialize(&message.data) {
+                    if let Some(user_id) =
+                        self.draw(|drawing, user_id| drawing.append(user_id, &mut append))
+                    {
+                        append.id = user_id;
+                        self.broadcast(PoolMessage::StrokeAppended(append));
+                    }
+                }
+            }
+            MessageType::MyStrokeEnd => {
+                if let Ok(mut end) = StrokeEnd::deserialize(&message.data) {
+                    if let Some(user_id) =
+                        self.draw(|drawing, user_id| drawing.end(user_id, end.stroke))
+                    {
+                        end.id = user_id;
+                        self.broadcast(PoolMessage::StrokeEnded(end));
+                    }
+                }
+            }
+            MessageType::MyStrokeErase => {
+                if let Ok(mut erase) = StrokeErase::deserialize(&message.data) {
+                    if let Some(user_id) =
+                        self.draw(|drawing, user_id| drawing.erase(user_id, erase.all))
+                    {
+                        erase.id = user_id;
+                        self.broadcast(PoolMessage::StrokeErased(erase));
+                    }
+                }
+            }
+            MessageType::MyStateUpdate => {
+                if let Ok(update) = RoomStateUpdate::deserialize(&message.data) {
+                    let Some(user_id) = *self.user_id.borrow() else {
+                        return;
+                    };
+                    if !self.state_limiter.borrow_mut().allow() {
+                        return;
+                    }
+                    let mut changed = None;
+                    self.with_pool(|pool| {
+                        changed = Some(pool.state.lock().unwrap().merge(user_id, update))
+                    });
+                    if let Some(changed) = changed.filter(|changed| !changed.entries.is_empty()) {
+                        // the sender learns its entries were stamped with its id
+                        self.reply(ctx, PoolMessage::StateUpdate(changed.clone()));
+                        self.broadcast(PoolMessage::StateUpdate(changed));
+                    }
+                }
+            }
+            MessageType::MyChatMessage => {
+                if let Ok(mut chat_message) = ChatMessage::deserialize(&message.data) {
+                    let Some(user_id) = *self.user_id.borrow() else {
+                        return;
+                    };
+                    chat_message.truncate();
+                    if chat_message.text.trim().is_empty()
+                        || !self.chat_limiter.borrow_mut().allow()
+                    {
+                        return;
+                    }
+                    let mut logged = None;
+                    self.with_pool(|pool| {
+                        let mut chat_log = pool.chat_log.lock().unwrap();
+                        logged = Some(chat_log.push(user_id, chat_message.text));
+                    });
+                    if let Some(logged) = logged {
+                        // the sender sees their message once it has its place in the log
+                        self.reply(ctx, PoolMessage::ChatMessageLogged(logged.clone()));
+                        self.broadcast(PoolMessage::ChatMessageLogged(logged));
+                    }
+                }
+            }
+            MessageType::MyModeration => {
+                if let Ok(moderation) = Moderation::deserialize(&message.data) {
+                    self.moderate(moderation);
+                }
+            }
+            MessageType::MyRoomConfig => {
+                if let Ok(config) = RoomConfig::deserialize(&message.data) {
+                    self.configure_room(config);
+                }
+            }
+            MessageType::MyBlockList => {
+                if let Ok(mut block_list) = BlockList::deserialize(&message.data) {
+                    block_list.users.truncate(MAX_BLOCKED);
+                    self.with_member(|member| *member.blocked.lock().unwrap() = block_list);
+                }
+            }
+            MessageType::ChatHistoryRequest => {
+                if let Ok(request) = ChatHistoryRequest::deserialize(&message.data) {
+                    let limit = request.limit.min(MAX_CHAT_HISTORY_PAGE) as usize;
+                    let mut history = None;
+                    self.with_pool(|pool| {
+                        history = Some(pool.chat_log.lock().unwrap().page(request.before, limit));
+                    });
+                    if let Some(history) = history {
+                        self.reply(ctx, PoolMessage::ChatHistoryPage(history));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records who this user follows and lets the room (including the
+    /// followed user) know. Following someone who isn't here is answered
+    /// with the user's unchanged presence, for their client to roll back.
+    fn follow(&self, follow: Follow, ctx: &mut ws::WebsocketContext<Self>) {
+        let (Some(user_id), Some(url)) = (*self.user_id.borrow(), self.url.borrow().clone()) else {
+            return;
+        };
+        if follow.target == Some(user_id) {
+            return;
+        }
+
+        let followed = {
+            let pools = self.messaging_pools.read().unwrap();
+            let pool = pools.get(&url).unwrap().read().unwrap();
+            let Some(member) = pool.members.get(&user_id) else {
+                return;
+            };
+            let mut presence = member.presence.lock().unwrap();
+            if follow
+                .target
+                .is_some_and(|target| !pool.members.contains_key(&target))
+            {
+                Err(*presence) // can't follow someone who isn't here
+            } else {
+                presence.following = follow.target;
+                Ok(*presence)
+            }
+        };
+
+        match followed {
+            Ok(presence) => self.broadcast(PoolMessage::PresenceUpdate(presence)),
+            Err(presence) => self.reply(ctx, PoolMessage::PresenceUpdate(presence)),
+        }
+    }
+
+    fn auth(&self, auth: Auth, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.user_id.borrow().is_some() || self.url.borrow().is_some() {
+            debug!("ignoring repeated auth");
+            return; // we are already authed, do nothing
+        }
 
-                if let (Some(user_id), Some(url)) =
-                    (self.user_id.borrow().as_ref(), self.url.borrow().as_ref())
+        // a new room's files are read without holding up every other room,
+        // placing the user is then tried again with the room at hand
+        let mut opened: Option<(String, Pool)> = None;
+        let (instance, key, pools) = loop {
+            let mut pools = self.messaging_pools.write().unwrap();
+            let now = Instant::now();
+            let mut loads = Vec::new();
+            for instance in 0.. {
+                let Some(pool) = pools.get(&instance_key(&auth.url, instance)) else {
+                    break;
+                };
+                let pool = pool.read().unwrap();
+                // only the connection holding an id can be replaced, from
+                // where it connected. After a network change the old socket
+                // keeps the id until the heartbeat drops it, the client
+                // retries until then.
+                if pool
+                    .members
+                    .get(&auth.id)
+                    .is_some_and(|member| member.fingerprint != self.fingerprint)
                 {
-                    let pools = self.messaging_pools.read().unwrap();
-                    let mut pool = pools.get(url).unwrap().write().unwrap();
-                    pool.remove(user_id);
+                    info!("refused a taken id");
+                    ctx.close(Some(CloseReason {
+                        code: CloseCode::Again,
+                        description: Some(String::from("id in use")),
+                    }));
+                    ctx.stop();
+                    return;
+                }
+                // the room's own owner bans from every instance, owners of
+                // overflow instances only from theirs
+                let banned =
+                    pool.moderation
+                        .lock()
+                        .unwrap()
+                        .is_banned(auth.id, self.fingerprint, now);
+                if banned && instance == 0 {
+                    info!("refused banned user");
+                    ctx.close(Some(policy_close("banned")));
+                    ctx.stop();
+                    return;
+                }
+                let max_members = pool.config.lock().unwrap().max_members;
+                loads.push(InstanceLoad {
+                    instance,
+                    members: pool.members.len(),
+                    capacity: max_members.map_or(self.config.max_room_size, |max| {
+                        self.config.max_room_size.min(max as usize)
+                    }),
+                    has_user: pool.members.contains_key(&auth.id),
+                    has_inviter: auth
+                        .invited_by
+                        .is_some_and(|inviter| pool.members.contains_key(&inviter)),
+                    banned,
+                });
+            }
+            let instance = instances::place(&loads);
+            let key = instance_key(&auth.url, instance);
+            if pools.contains_key(&key) {
+                break (instance, key, pools);
+            }
+            match opened.take() {
+                Some((opened_key, pool)) if opened_key == key => {
+                    pools.insert(key.clone(), RwLock::new(pool));
+                    break (instance, key, pools);
+                }
+                _ => {
+                    drop(pools);
+                    // overflow instances get their own files
+                    opened = Some((key.clone(), self.open_pool(&key)));
                 }
+            }
+        };
 
-                if let (Some(user_id), Some(url)) =
-                    (self.user_id.borrow().as_ref(), self.url.borrow().as_ref())
-                {
-                    let pools = self.messaging_pools.read().unwrap();
-                    let pool = pools.get(url).unwrap().read().unwrap();
-                    for (_, sender) in pool.iter() {
-                        let s_lock = sender.lock().unwrap();
-                        let _ = s_lock.send(PoolMessage::DespawnEntity(Despawn { id: *user_id }));
+        // the room is logged by hash, like its files on disk
+        self.span
+            .record("room", field::display(admin::room_id(&key)));
+        self.span.record("user", auth.id);
+
+        let mut pool = pools.get(&key).unwrap().write().unwrap();
+
+        *self.user_id.borrow_mut() = Some(auth.id);
+        *self.url.borrow_mut() = Some(key);
+        let (tx, rx): (Sender<Queued>, Receiver<Queued>) = mpsc::channel();
+        let tx = Outbox(tx);
+
+        // startup an interval
+        let metrics = self.metrics.clone();
+        let span = self.span.clone();
+        ctx.run_interval(Duration::from_millis(10), move |_, ctx| {
+            for (queued_at, outgoing) in rx.try_iter() {
+                match outgoing {
+                    Outgoing::Message(pool_message) => {
+                        metrics.delivered_after(queued_at.elapsed());
+                        let message = pool_message.into_message();
+                        let bytes = message.serialize().unwrap();
+                        metrics.sent(&message.message_type, bytes.len());
+                        ctx.binary(bytes);
+                    }
+                    Outgoing::Close(reason) => {
+                        info!(parent: &span, reason = ?reason, "closed by the server");
+                        ctx.close(Some(reason));
+                        ctx.stop();
+                        return;
+                    }
+                }
+            }
+        });
+
+        if pool.members.is_empty() {
+            self.metrics.rooms.inc();
+        }
+
+        // insert the sender into the pool
+        // other thread/sockets will be sending through this
+        let replaced = pool.members.insert(
+            auth.id,
+            Member {
+                connection: self.connection,
+                joined_at: Instant::now(),
+                received: self.received.clone(),
+                fingerprint: self.fingerprint,
+                muted: self.muted.clone(),
+                sender: Mutex::new(Outbox(tx.0.clone())),
+                viewport: Mutex::new(None),
+                presence: Mutex::new(Presence {
+                    id: auth.id,
+                    following: None,
+                }),
+                selection: Mutex::new(None),
+                blocked: Mutex::new(BlockList::default()),
+            },
+        );
+        if let Some(replaced) = &replaced {
+            replaced
+                .sender
+                .lock()
+                .unwrap()
+                .close(policy_close("replaced"));
+        }
+
+        // the room already knows a member we replaced
+        for (id, member) in pool.members.iter() {
+            if *id == auth.id || replaced.is_some() {
+                continue;
+            }
+            let s_lock = member.sender.lock().unwrap();
+            let _ = s_lock.send(PoolMessage::SpawnEntity(Spawn {
+                id: auth.id,
+                icon: auth.url.clone(),
+            }));
+        }
+        self.metrics.room_size.observe(pool.members.len() as f64);
+        info!(members = pool.members.len(), instance, "joined room");
+
+        let _ = tx.send(PoolMessage::Joined(AuthAck {
+            instance,
+            members: pool.members.len() as u32,
+        }));
+
+        for (id, member) in pool.members.iter() {
+            if *id == auth.id {
+                continue;
+            }
+            let _ = tx.send(PoolMessage::SpawnEntity(Spawn {
+                id: *id,
+                icon: String::from("test"),
+            }));
+            if let Some(viewport) = *member.viewport.lock().unwrap() {
+                let _ = tx.send(PoolMessage::ViewportUpdate(viewport));
+            }
+            let presence = *member.presence.lock().unwrap();
+            if presence.following.is_some() {
+                let _ = tx.send(PoolMessage::PresenceUpdate(presence));
+            }
+            if let Some(selection) = member.selection.lock().unwrap().clone() {
+                let _ = tx.send(PoolMessage::SelectionUpdate(selection));
+            }
+        }
+
+        let history = pool
+            .chat_log
+            .lock()
+            .unwrap()
+            .page(None, MAX_CHAT_HISTORY_PAGE as usize);
+        let _ = tx.send(PoolMessage::ChatHistoryPage(history));
+
+        // notes written before a reload stay editable by their author
+        let annotations = {
+            let mut annotations = pool.annotations.lock().unwrap();
+            let present = |id| pool.members.contains_key(&id);
+            for adopted in annotations.adopt(auth.id, self.fingerprint, present) {
+                notify_room(
+                    &pool,
+                    PoolMessage::AnnotationUpdate(AnnotationChange::Upsert(adopted)),
+                );
+            }
+            annotations.all().to_vec()
+        };
+        let _ = tx.send(PoolMessage::AnnotationList(AnnotationSync { annotations }));
+
+        let strokes = pool.drawing.lock().unwrap().all().to_vec();
+        let _ = tx.send(PoolMessage::StrokeList(StrokeSync { strokes }));
+
+        let snapshot = pool.state.lock().unwrap().snapshot();
+        let _ = tx.send(PoolMessage::StateUpdate(snapshot));
+
+        // the first to join runs the room, everyone learns who that is
+        let mut moderation = pool.moderation.lock().unwrap();
+        match moderation.owner() {
+            Some(owner) if owner.user != auth.id => {
+                let _ = tx.send(PoolMessage::ModerationNotice(ownership(owner.user)));
+            }
+            // nobody runs the room, or the connection we replaced did and
+            // ownership stays with connections, not ids
+            _ => {
+                let owner = longest_present(&pool, auth.id).unwrap_or(Owner {
+                    user: auth.id,
+                    connection: self.connection,
+                });
+                moderation.set_owner(Some(owner));
+                notify_room(&pool, PoolMessage::ModerationNotice(ownership(owner.user)));
+            }
+        }
+        for (id, member) in pool.members.iter() {
+            if !moderation.is_muted(*id, member.fingerprint) {
+                continue;
+            }
+            let notice = PoolMessage::ModerationNotice(Moderation {
+                id: 0,
+                target: *id,
+                action: ModerationAction::Mute,
+                seconds: 0,
+            });
+            if *id == auth.id {
+                // muted before, maybe under another id
+                self.muted.store(true, Ordering::Relaxed);
+                notify_room(&pool, notice);
+            } else {
+                let _ = tx.send(notice);
+            }
+        }
+    }
+
+    /// Applies `moderation` if we own the room, then tells the room.
+    fn moderate(&self, mut moderation: Moderation) {
+        let Some(user_id) = *self.user_id.borrow() else {
+            return;
+        };
+        if moderation.target == user_id {
+            return;
+        }
+        moderation.id = user_id;
+        moderation.seconds = moderation.seconds.min(MAX_BAN.as_secs() as u32);
+
+        self.with_pool(|pool| {
+            let mut room = pool.moderation.lock().unwrap();
+            if !room.is_owner(user_id, self.connection) {
+                return;
+            }
+            // everything is done to someone in the room
+            let Some(target) = pool.members.get(&moderation.target) else {
+                return;
+            };
+            match moderation.action {
+                ModerationAction::Owner => room.set_owner(Some(Owner {
+                    user: moderation.target,
+                    connection: target.connection,
+                })),
+                ModerationAction::Mute | ModerationAction::Unmute => {
+                    let muted = moderation.action == ModerationAction::Mute;
+                    if !room.set_muted(moderation.target, target.fingerprint, muted) {
+                        return;
                     }
+                    target.muted.store(muted, Ordering::Relaxed);
+                }
+                ModerationAction::Kick => {}
+                ModerationAction::Ban => {
+                    let duration = Duration::from_secs(moderation.seconds.into());
+                    room.ban(
+                        moderation.target,
+                        target.fingerprint,
+                        duration,
+                        Instant::now(),
+                    );
                 }
             }
+
+            info!(
+                target = moderation.target,
+                action = moderation.action.name(),
+                "moderated"
+            );
+            notify_room(pool, PoolMessage::ModerationNotice(moderation));
+            if matches!(
+                moderation.action,
+                ModerationAction::Kick | ModerationAction::Ban
+            ) {
+                let description = match moderation.action {
+                    ModerationAction::Ban => "banned",
+                    _ => "kicked",
+                };
+                target
+                    .sender
+                    .lock()
+                    .unwrap()
+                    .close(policy_close(description));
+            }
+        });
+    }
+
+    /// Applies the limits of the room's site policy, if we own the room. The
+    /// owner is the first to join, so the policy is set before it fills up.
+    fn configure_room(&self, config: RoomConfig) {
+        let Some(user_id) = *self.user_id.borrow() else {
+            return;
+        };
+        // clients send whatever they like, owners can't lower it too far
+        let min_members = self.config.min_room_size.try_into().unwrap_or(u32::MAX);
+        let config = RoomConfig {
+            max_members: config.max_members.map(|max| max.max(min_members)),
+        };
+        self.with_pool(|pool| {
+            if pool
+                .moderation
+                .lock()
+                .unwrap()
+                .is_owner(user_id, self.connection)
+            {
+                info!(max_members = config.max_members, "room configured");
+                *pool.config.lock().unwrap() = config;
+            }
+        });
+    }
+
+    /// Runs `f` with this connection's pool, if authed.
+    fn with_pool(&self, f: impl FnOnce(&Pool)) {
+        if let Some(url) = self.url.borrow().as_ref() {
+            let pools = self.messaging_pools.read().unwrap();
+            let pool = pools.get(url).unwrap().read().unwrap();
+            f(&pool);
+        }
+    }
+
+    /// Runs `f` with this connection's entry in its pool, if authed.
+    fn with_member(&self, f: impl FnOnce(&Member)) {
+        if let Some(user_id) = *self.user_id.borrow() {
+            self.with_pool(|pool| {
+                if let Some(member) = pool.members.get(&user_id) {
+                    f(member);
+                }
+            });
+        }
+    }
+
+    /// Runs `f` with the pool's drawing if this connection is authed and
+    /// within its drawing rate limit. Returns our user id if `f` did.
+    fn draw(&self, f: impl FnOnce(&mut Drawing, u64) -> bool) -> Option<u64> {
+        let user_id = (*self.user_id.borrow())?;
+        if !self.draw_limiter.borrow_mut().allow() {
+            return None;
+        }
+        let mut applied = false;
+        self.with_pool(|pool| applied = f(&mut pool.drawing.lock().unwrap(), user_id));
+        applied.then_some(user_id)
+    }
+
+    /// Sends `pool_message` straight back to this connection.
+    fn reply(&self, ctx: &mut ws::WebsocketContext<Self>, pool_message: PoolMessage) {
+        let message = pool_message.into_message();
+        let bytes = message.serialize().unwrap();
+        self.metrics.sent(&message.message_type, bytes.len());
+        ctx.binary(bytes);
+    }
+
+    /// Sends `pool_message` to every other member of this connection's pool.
+    fn broadcast(&self, pool_message: PoolMessage) {
+        if let (Some(user_id), Some(url)) =
+            (self.user_id.borrow().as_ref(), self.url.borrow().as_ref())
+        {
+            let pools = self.messaging_pools.read().unwrap();
+            let pool = pools.get(url).unwrap().read().unwrap();
+            let mut recipients = 0;
+            for (id, member) in pool.members.iter() {
+                if id == user_id
+                    || (pool_message.blockable() && member.blocked.lock().unwrap().hides(*user_id))
+                {
+                    continue;
+                }
+                let s_lock = member.sender.lock().unwrap();
+                let _ = s_lock.send(pool_message.clone());
+                recipients += 1;
+            }
+            trace!(recipients, "broadcast");
+        }
+    }
+
+    /// A new, empty pool for room `key`, with what its stores load from disk.
+    fn open_pool(&self, key: &str) -> Pool {
+        let writer = FileWriter::clone(&self.writer);
+        Pool {
+            members: HashMap::new(),
+            chat_log: Mutex::new(ChatLog::open(
+                self.config.chat_history_dir.as_deref(),
+                key,
+                self.config.chat_history_size,
+                writer.clone(),
+            )),
+            annotations: Mutex::new(AnnotationStore::open(
+                self.config.annotation_dir.as_deref(),
+                key,
+                self.config.annotation_limit,
+                writer.clone(),
+            )),
+            drawing: Mutex::new(Drawing::open(
+                self.config.drawing_dir.as_deref(),
+                key,
+                self.config.stroke_limit,
+                writer.clone(),
+            )),
+            state: Mutex::new(RoomState::open(
+                self.config.room_state_dir.as_deref(),
+                key,
+                self.config.room_state_key_limit,
+                writer,
+            )),
+            moderation: Mutex::new(RoomModeration::new()),
+            config: Mutex::new(RoomConfig::default()),
+        }
+    }
+
+    /// Takes this connection out of its pool, once.
+    fn leave(&self) {
+        let (Some(user_id), Some(url)) = (self.user_id.take(), self.url.take()) else {
+            return;
+        };
+        let pools = self.messaging_pools.read().unwrap();
+        let mut pool = pools.get(&url).unwrap().write().unwrap();
+        if pool
+            .members
+            .get(&user_id)
+            .is_none_or(|member| member.connection != self.connection)
+        {
+            // the user reconnected and a newer connection took our place
+            return;
+        }
+
+        pool.members.remove(&user_id);
+        info!(members = pool.members.len(), "left room");
+        if pool.members.is_empty() {
+            self.metrics.rooms.dec();
+            // whoever opens the room next brings its site's policy again
+            *pool.config.lock().unwrap() = RoomConfig::default();
+        }
+        pool.drawing.lock().unwrap().end_all(user_id);
+
+        for member in pool.members.values() {
+            let s_lock = member.sender.lock().unwrap();
+            let _ = s_lock.send(PoolMessage::DespawnEntity(Despawn { id: user_id }));
+        }
+
+        // nobody follows someone who is gone, or gets it replayed on joining
+        for member in pool.members.values() {
+            let mut presence = member.presence.lock().unwrap();
+            if presence.following == Some(user_id) {
+                presence.following = None;
+                notify_room(&pool, PoolMessage::PresenceUpdate(*presence));
+            }
+        }
+
+        // whoever has been around the longest takes over the room
+        let mut moderation = pool.moderation.lock().unwrap();
+        if moderation.is_owner(user_id, self.connection) {
+            let next = longest_present(&pool, user_id);
+            moderation.set_owner(next);
+            if let Some(next) = next {
+                notify_room(&pool, PoolMessage::ModerationNotice(ownership(next.user)));
+            }
+        }
+    }
+}
+
+/// Handler for ws::Message message
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let span = self.span.clone();
+        let _entered = span.enter();
+        *self.last_heard.borrow_mut() = Instant::now();
+        match msg {
+            Ok(ws::Message::Binary(bin)) => match Message::deserialize(&bin) {
+                Ok(message) => {
+                    self.received.fetch_add(1, Ordering::Relaxed);
+                    trace!(message_type = ?message.message_type, bytes = bin.len(), "received");
+                    self.metrics
+                        .received(Some(&message.message_type), bin.len());
+                    self.handle_message(message, ctx);
+                }
+                Err(e) => {
+                    debug!(bytes = bin.len(), error = %e, "failed to decode message");
+                    self.metrics.received(None, bin.len());
+                }
+            },
+            Ok(ws::Message::Close(rsn)) => {
+                info!(reason = ?rsn, "closing");
+
+                self.leave();
+            }
+            Ok(ws::Message::Ping(bytes)) => ctx.pong(&bytes),
             _ => (),
         }
     }
@@ -205,40 +991,349 @@ async fn index(
     req: HttpRequest,
     stream: web::Payload,
     messaging_pools: Data<MessagingPools>,
+    config: Data<Config>,
+    metrics: Data<Metrics>,
+    health: Data<Health>,
+    writer: Data<FileWriter>,
 ) -> Result<HttpResponse, Error> {
-    let resp = ws::start(
+    if health.is_draining() {
+        return Ok(HttpResponse::ServiceUnavailable().finish());
+    }
+
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    let span = info_span!(
+        "connection",
+        id = connection,
+        room = field::Empty,
+        user = field::Empty,
+    );
+    let fingerprint = fingerprint(&req, config.behind_proxy);
+    ws::start(
         MyWs {
             user_id: RefCell::new(None),
             url: RefCell::new(None),
             messaging_pools,
+            config,
+            metrics,
+            health,
+            writer,
+            connection,
+            going_away: RefCell::new(false),
+            received: Arc::new(AtomicU64::new(0)),
+            last_heard: RefCell::new(Instant::now()),
+            fingerprint,
+            muted: Arc::new(AtomicBool::new(false)),
+            span,
+            chat_limiter: RefCell::new(RateLimiter::new(CHAT_BURST, CHAT_PER_SECOND)),
+            ping_limiter: RefCell::new(RateLimiter::new(PING_BURST, PING_PER_SECOND)),
+            reaction_limiter: RefCell::new(RateLimiter::new(REACTION_BURST, REACTION_PER_SECOND)),
+            selection_limiter: RefCell::new(RateLimiter::new(
+                SELECTION_BURST,
+                SELECTION_PER_SECOND,
+            )),
+            annotation_limiter: RefCell::new(RateLimiter::new(
+                ANNOTATION_BURST,
+                ANNOTATION_PER_SECOND,
+            )),
+            draw_limiter: RefCell::new(RateLimiter::new(DRAW_BURST, DRAW_PER_SECOND)),
+            state_limiter: RefCell::new(RateLimiter::new(STATE_BURST, STATE_PER_SECOND)),
         },
         &req,
         stream,
-    );
+    )
+}
 
-    println!("opening connection");
+/// Where a connection comes from, so a ban outlasts the user's id. Behind
+/// fly's proxy every peer is the proxy, the client is in `Fly-Client-IP`.
+fn fingerprint(req: &HttpRequest, behind_proxy: bool) -> u64 {
+    let forwarded = || {
+        req.headers()
+            .get("Fly-Client-IP")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let address = behind_proxy
+        .then(forwarded)
+        .flatten()
+        .or_else(|| req.peer_addr().map(|addr| addr.ip().to_string()))
+        .unwrap_or_default();
+    fnv1a(address.as_bytes())
+}
 
-    resp
+/// Ids told apart in the logs, unlike user ids they are never reused.
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+async fn metrics_handler(metrics: Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
 }
 
 pub async fn start() -> std::io::Result<()> {
     let map: MessagingPools = RwLock::new(HashMap::new());
     let data = Data::new(map);
+    let config = Config::from_env();
+    logging::init(&config);
+    let config = Data::new(config);
+    let metrics = Data::new(Metrics::new());
+    let health = Data::new(Health::new());
+    let writer = Data::new(FileWriter::spawn());
+    let flush = writer.clone();
+    let drain_timeout = config.drain_timeout;
+    let (drain_health, drain_metrics) = (health.clone(), metrics.clone());
+
+    // without a token the admin API is only served on a listener of its own
+    // on loopback, a proxy on this machine makes every client look local
+    let public_admin = config.admin_token.is_some();
+    let admin_server = if public_admin {
+        None
+    } else {
+        let (data, config) = (data.clone(), config.clone());
+        let admin_port = config.admin_port;
+        Some(
+            HttpServer::new(move || {
+                App::new()
+                    .app_data(data.clone())
+                    .app_data(config.clone())
+                    .service(web::scope("/admin").configure(admin::configure))
+            })
+            .disable_signals()
+            .bind(("127.0.0.1", admin_port))?
+            .run(),
+        )
+    };
+    let admin_handle = admin_server.as_ref().map(|server| server.handle());
+    if let Some(admin_server) = admin_server {
+        actix_web::rt::spawn(admin_server);
+    }
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
+            .app_data(config.clone())
+            .app_data(metrics.clone())
+            .app_data(health.clone())
+            .app_data(writer.clone())
             .route("/ws/", web::get().to(index))
+            .route("/metrics", web::get().to(metrics_handler))
+            .route("/healthz", web::get().to(health::healthz))
+            .route("/readyz", web::get().to(health::readyz))
+            .configure(|cfg| {
+                if public_admin {
+                    cfg.service(web::scope("/admin").configure(admin::configure));
+                }
+            })
     })
+    // we drain connections on SIGTERM ourselves, see `shutdown`
+    .disable_signals()
     .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .run();
+
+    actix_web::rt::spawn(shutdown::drain_on_signal(
+        server.handle(),
+        drain_health,
+        drain_metrics,
+        drain_timeout,
+    ));
+    server.await?;
+    if let Some(admin_handle) = admin_handle {
+        admin_handle.stop(true).await;
+    }
+    // rooms written to last are on disk before we exit
+    flush.flush();
+    Ok(())
+}
+
+/// Queues messages for a member's socket, noting when each was queued so
+/// the fan-out latency can be measured once it is sent.
+struct Outbox(Sender<Queued>);
+
+type Queued = (Instant, Outgoing);
+
+/// What a member's socket is asked to do.
+enum Outgoing {
+    Message(PoolMessage),
+    /// Closes the socket, e.g. when the member is kicked.
+    Close(CloseReason),
+}
+
+impl Outbox {
+    /// Returns false if the member's socket has gone away.
+    fn send(&self, message: PoolMessage) -> bool {
+        self.0
+            .send((Instant::now(), Outgoing::Message(message)))
+            .is_ok()
+    }
+
+    /// Has the member's socket close with `reason`.
+    fn close(&self, reason: CloseReason) -> bool {
+        self.0
+            .send((Instant::now(), Outgoing::Close(reason)))
+            .is_ok()
+    }
+}
+
+/// Closes a socket the server doesn't want back, clients don't reconnect.
+fn policy_close(description: &str) -> CloseReason {
+    CloseReason {
+        code: CloseCode::Policy,
+        description: Some(String::from(description)),
+    }
+}
+
+/// Sends `pool_message` to every member of `pool`.
+fn notify_room(pool: &Pool, pool_message: PoolMessage) {
+    for member in pool.members.values() {
+        let s_lock = member.sender.lock().unwrap();
+        let _ = s_lock.send(pool_message.clone());
+    }
 }
 
-type MessagingPools = RwLock<HashMap<String, RwLock<HashMap<u64, Mutex<Sender<PoolMessage>>>>>>;
+/// The connection of whoever has been in `pool` the longest, besides `except`.
+fn longest_present(pool: &Pool, except: u64) -> Option<Owner> {
+    pool.members
+        .iter()
+        .filter(|(id, _)| **id != except)
+        .min_by_key(|(_, member)| member.joined_at)
+        .map(|(id, member)| Owner {
+            user: *id,
+            connection: member.connection,
+        })
+}
+
+/// Tells that `owner` now runs the room.
+fn ownership(owner: u64) -> Moderation {
+    Moderation {
+        id: 0,
+        target: owner,
+        action: ModerationAction::Owner,
+        seconds: 0,
+    }
+}
 
+/// A connection that joined a pool.
+struct Member {
+    connection: u64,
+    joined_at: Instant,
+    /// Messages received from the member's connection.
+    received: Arc<AtomicU64>,
+    fingerprint: u64,
+    muted: Arc<AtomicBool>,
+    sender: Mutex<Outbox>,
+    /// Last viewport the member shared, replayed to members who join later.
+    viewport: Mutex<Option<Viewport>>,
+    presence: Mutex<Presence>,
+    /// Current text selection, replayed to members who join later.
+    selection: Mutex<Option<TextSelection>>,
+    /// Peers whose traffic the member asked not to be sent.
+    blocked: Mutex<BlockList>,
+}
+
+/// Everyone connected under the same url, plus what the room remembers.
+struct Pool {
+    members: HashMap<u64, Member>,
+    chat_log: Mutex<ChatLog>,
+    annotations: Mutex<AnnotationStore>,
+    drawing: Mutex<Drawing>,
+    state: Mutex<RoomState>,
+    moderation: Mutex<RoomModeration>,
+    /// Limits the room's site asked for, see `RoomConfig`.
+    config: Mutex<RoomConfig>,
+}
+
+type MessagingPools = RwLock<HashMap<String, RwLock<Pool>>>;
+
+#[derive(Clone)]
 enum PoolMessage {
     LocationUpdate(Location),
     SpawnEntity(Spawn),
     DespawnEntity(Despawn),
+    ViewportUpdate(Viewport),
+    PresenceUpdate(Presence),
+    ChatUpdate(Chat),
+    ChatMessageLogged(ChatMessage),
+    ChatHistoryPage(ChatHistory),
+    PingEffect(Ping),
+    ReactionEffect(Reaction),
+    SelectionUpdate(TextSelection),
+    AnnotationUpdate(AnnotationChange),
+    AnnotationList(AnnotationSync),
+    StrokeStarted(StrokeStart),
+    StrokeAppended(StrokeAppend),
+    StrokeEnded(StrokeEnd),
+    StrokeErased(StrokeErase),
+    StrokeList(StrokeSync),
+    StateUpdate(RoomStateUpdate),
+    GoingAwayNotice(GoingAway),
+    ModerationNotice(Moderation),
+    Joined(AuthAck),
+}
+
+impl PoolMessage {
+    /// Whether this is about its sender alone, so members who blocked them
+    /// can go without it. Room content has to reach everyone to stay in sync.
+    fn blockable(&self) -> bool {
+        matches!(
+            self,
+            PoolMessage::LocationUpdate(_)
+                | PoolMessage::ViewportUpdate(_)
+                | PoolMessage::PresenceUpdate(_)
+                | PoolMessage::ChatUpdate(_)
+                | PoolMessage::ChatMessageLogged(_)
+                | PoolMessage::PingEffect(_)
+                | PoolMessage::ReactionEffect(_)
+                | PoolMessage::SelectionUpdate(_)
+        )
+    }
+
+    fn into_message(self) -> Message {
+        let (message_type, data) = match self {
+            PoolMessage::LocationUpdate(location) => {
+                (MessageType::UserLocation, location.serialize())
+            }
+            PoolMessage::SpawnEntity(spawn) => (MessageType::Spawn, spawn.serialize()),
+            PoolMessage::DespawnEntity(despawn) => (MessageType::Despawn, despawn.serialize()),
+            PoolMessage::ViewportUpdate(viewport) => {
+                (MessageType::UserViewport, viewport.serialize())
+            }
+            PoolMessage::PresenceUpdate(presence) => (MessageType::Presence, presence.serialize()),
+            PoolMessage::ChatUpdate(chat) => (MessageType::UserChat, chat.serialize()),
+            PoolMessage::ChatMessageLogged(chat_message) => {
+                (MessageType::UserChatMessage, chat_message.serialize())
+            }
+            PoolMessage::ChatHistoryPage(history) => {
+                (MessageType::ChatHistory, history.serialize())
+            }
+            PoolMessage::PingEffect(ping) => (MessageType::UserPing, ping.serialize()),
+            PoolMessage::ReactionEffect(reaction) => {
+                (MessageType::UserReaction, reaction.serialize())
+            }
+            PoolMessage::SelectionUpdate(selection) => {
+                (MessageType::UserSelection, selection.serialize())
+            }
+            PoolMessage::AnnotationUpdate(change) => {
+                (MessageType::UserAnnotation, change.serialize())
+            }
+            PoolMessage::AnnotationList(sync) => (MessageType::AnnotationSync, sync.serialize()),
+            PoolMessage::StrokeStarted(start) => (MessageType::UserStrokeStart, start.serialize()),
+            PoolMessage::StrokeAppended(append) => {
+                (MessageType::UserStrokeAppend, append.serialize())
+            }
+            PoolMessage::StrokeEnded(end) => (MessageType::UserStrokeEnd, end.serialize()),
+            PoolMessage::StrokeErased(erase) => (MessageType::UserStrokeErase, erase.serialize()),
+            PoolMessage::StrokeList(sync) => (MessageType::StrokeSync, sync.serialize()),
+            PoolMessage::StateUpdate(update) => (MessageType::UserStateUpdate, update.serialize()),
+            PoolMessage::GoingAwayNotice(going_away) => {
+                (MessageType::GoingAway, going_away.serialize())
+            }
+            PoolMessage::Joined(ack) => (MessageType::AuthAck, ack.serialize()),
+            PoolMessage::ModerationNotice(moderation) => {
+                (MessageType::UserModeration, moderation.serialize())
+            }
+        };
+        Message {
+            message_type,
+            data: data.unwrap(),
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use shared::MessageType;
+use std::time::Duration;
+use tracing::warn;
+
+/// Server metrics, exposed in the Prometheus text format on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    /// Open websocket connections.
+    pub connections: IntGauge,
+    /// Rooms with a pool on this server.
+    pub rooms: IntGauge,
+    /// Members in a room, observed whenever someone joins.
+    pub room_size: Histogram,
+    messages_in: IntCounterVec,
+    messages_out: IntCounterVec,
+    bytes_in: IntCounter,
+    bytes_out: IntCounter,
+    /// Binary frames that weren't a `Message`.
+    pub decode_failures: IntCounter,
+    /// Time from queueing a message for a member to handing it to their socket.
+    fanout_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        let connections =
+            IntGauge::new("multiplayer_connections", "Open websocket connections.").unwrap();
+        let rooms = IntGauge::new("multiplayer_rooms", "Rooms with at least one member.").unwrap();
+        let room_size = Histogram::with_opts(
+            HistogramOpts::new(
+                "multiplayer_room_size",
+                "Members in a room, observed when someone joins.",
+            )
+            .buckets(vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0]),
+        )
+        .unwrap();
+        let messages_in = IntCounterVec::new(
+            Opts::new("multiplayer_messages_in_total", "Messages received."),
+            &["message_type"],
+        )
+        .unwrap();
+        let messages_out = IntCounterVec::new(
+            Opts::new("multiplayer_messages_out_total", "Messages sent."),
+            &["message_type"],
+        )
+        .unwrap();
+        let bytes_in =
+            IntCounter::new("multiplayer_bytes_in_total", "Websocket bytes received.").unwrap();
+        let bytes_out =
+            IntCounter::new("multiplayer_bytes_out_total", "Websocket bytes sent.").unwrap();
+        let decode_failures = IntCounter::new(
+            "multiplayer_decode_failures_total",
+            "Binary frames that could not be decoded.",
+        )
+        .unwrap();
+        let fanout_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "multiplayer_fanout_latency_seconds",
+                "Time from queueing a message for a member to sending it.",
+            )
+            .buckets(vec![
+                0.001, 0.0025, 0.005, 0.01, 0.015, 0.025, 0.05, 0.1, 0.25, 1.0,
+            ]),
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(rooms.clone())).unwrap();
+        registry.register(Box::new(room_size.clone())).unwrap();
+        registry.register(Box::new(messages_in.clone())).unwrap();
+        registry.register(Box::new(messages_out.clone())).unwrap();
+        registry.register(Box::new(bytes_in.clone())).unwrap();
+        registry.register(Box::new(bytes_out.clone())).unwrap();
+        registry
+            .register(Box::new(decode_failures.clone()))
+            .unwrap();
+        registry.register(Box::new(fanout_latency.clone())).unwrap();
+
+        Metrics {
+            registry,
+            connections,
+            rooms,
+            room_size,
+            messages_in,
+            messages_out,
+            bytes_in,
+            bytes_out,
+            decode_failures,
+            fanout_latency,
+        }
+    }
+
+    /// Counts a binary frame of `bytes` received, decoded as `message_type`.
+    pub fn received(&self, message_type: Option<&MessageType>, bytes: usize) {
+        self.bytes_in.inc_by(bytes as u64);
+        match message_type {
+            Some(message_type) => self
+                .messages_in
+                .with_label_values(&[&format!("{:?}", message_type)])
+                .inc(),
+            None => self.decode_failures.inc(),
+        }
+    }
+
+    /// Counts a message of `bytes` sent.
+    pub fn sent(&self, message_type: &MessageType, bytes: usize) {
+        self.bytes_out.inc_by(bytes as u64);
+        self.messages_out
+            .with_label_values(&[&format!("{:?}", message_type)])
+            .inc();
+    }
+
+    pub fn delivered_after(&self, latency: Duration) {
+        self.fanout_latency.observe(latency.as_secs_f64());
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        let encoder = TextEncoder::new();
+        if let Err(e) = encoder.encode(&self.registry.gather(), &mut buffer) {
+            warn!(error = %e, "failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counted_messages() {
+        let metrics = Metrics::new();
+        metrics.received(Some(&MessageType::MyLocation), 20);
+        metrics.received(None, 3);
+        metrics.sent(&MessageType::UserLocation, 20);
+
+        let text = metrics.render();
+        assert!(text.contains("multiplayer_messages_in_total{message_type=\"MyLocation\"} 1"));
+        assert!(text.contains("multiplayer_bytes_in_total 23"));
+        assert!(text.contains("multiplayer_decode_failures_total 1"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+/// Longest ban a room owner can hand out.
+pub const MAX_BAN: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Who runs a room and who they muted or banned. Kept in memory only, a
+/// room starts over once the server restarts.
+pub struct RoomModeration {
+    owner: Option<Owner>,
+    mutes: Vec<Mute>,
+    /// Users the owner unmuted while someone else muted connects like them.
+    unmuted: Vec<u64>,
+    bans: Vec<Ban>,
+}
+
+/// The owner's connection. Anyone can claim a user id, so taking over a
+/// connection's id doesn't make its owner.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Owner {
+    pub user: u64,
+    pub connection: u64,
+}
+
+/// Like a ban, a mute follows where the user connects from, a reload
+/// doesn't lift it.
+struct Mute {
+    user: u64,
+    fingerprint: u64,
+}
+
+/// Keeps a user out by id and by where they connect from, so a new id
+/// doesn't get them back in.
+struct Ban {
+    user: u64,
+    fingerprint: u64,
+    until: Instant,
+}
+
+impl RoomModeration {
+    pub fn new() -> RoomModeration {
+        RoomModeration {
+            owner: None,
+            mutes: Vec::new(),
+            unmuted: Vec::new(),
+            bans: Vec::new(),
+        }
+    }
+
+    pub fn owner(&self) -> Option<Owner> {
+        self.owner
+    }
+
+    pub fn set_owner(&mut self, owner: Option<Owner>) {
+        self.owner = owner;
+    }
+
+    /// Whether `connection` of `user` runs the room.
+    pub fn is_owner(&self, user: u64, connection: u64) -> bool {
+        self.owner == Some(Owner { user, connection })
+    }
+
+    /// Whether `user`, or anyone connecting like them, is muted.
+    pub fn is_muted(&self, user: u64, fingerprint: u64) -> bool {
+        self.mutes.iter().any(|mute| {
+            mute.user == user || (mute.fingerprint == fingerprint && !self.unmuted.contains(&user))
+        })
+    }
+
+    /// Mutes or unmutes `user`, who connects from `fingerprint`, returning
+    /// whether that changed anything. Others connecting like them stay as
+    /// they are.
+    pub fn set_muted(&mut self, user: u64, fingerprint: u64, muted: bool) -> bool {
+        let was_muted = self.is_muted(user, fingerprint);
+        if muted {
+            self.unmuted.retain(|unmuted| *unmuted != user);
+            if !self.is_muted(user, fingerprint) {
+                self.mutes.push(Mute { user, fingerprint });
+            }
+        } else if was_muted {
+            self.mutes.retain(|mute| mute.user != user);
+            if self.is_muted(user, fingerprint) {
+                self.unmuted.push(user);
+            }
+        }
+        muted != was_muted
+    }
+
+    pub fn ban(&mut self, user: u64, fingerprint: u64, duration: Duration, now: Instant) {
+        self.bans.push(Ban {
+            user,
+            fingerprint,
+            until: now + duration.min(MAX_BAN),
+        });
+    }
+
+    /// Whether `user`, or anyone connecting like them, is banned. Forgets
+    /// bans that ran out.
+    pub fn is_banned(&mut self, user: u64, fingerprint: u64, now: Instant) -> bool {
+        self.bans.retain(|ban| ban.until > now);
+        self.bans
+            .iter()
+            .any(|ban| ban.user == user || ban.fingerprint == fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_match_user_or_fingerprint_until_they_expire() {
+        let mut moderation = RoomModeration::new();
+        let now = Instant::now();
+        moderation.ban(1, 100, Duration::from_secs(60), now);
+
+        assert!(moderation.is_banned(1, 200, now));
+        assert!(moderation.is_banned(2, 100, now));
+        assert!(!moderation.is_banned(2, 200, now));
+        assert!(!moderation.is_banned(1, 100, now + Duration::from_secs(61)));
+
+        // mutes outlast a new id too
+        assert!(moderation.set_muted(3, 300, true));
+        assert!(!moderation.set_muted(3, 300, true));
+        assert!(moderation.is_muted(3, 300));
+        assert!(moderation.is_muted(4, 300));
+        assert!(!moderation.is_muted(4, 400));
+
+        // unmuting someone leaves others muted who connect like them
+        assert!(moderation.set_muted(4, 300, false));
+        assert!(!moderation.is_muted(4, 300));
+        assert!(moderation.is_muted(5, 300));
+        assert!(moderation.set_muted(4, 300, true));
+        assert!(moderation.is_muted(4, 300));
+    }
+
+    #[test]
+    fn claiming_the_owners_id_doesnt_make_an_owner() {
+        let mut moderation = RoomModeration::new();
+        moderation.set_owner(Some(Owner {
+            user: 1,
+            connection: 10,
+        }));
+
+        assert!(moderation.is_owner(1, 10));
+        // a second Auth with the owner's id comes from another connection
+        assert!(!moderation.is_owner(1, 11));
+        assert!(!moderation.is_owner(2, 10));
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Instant;
+
+/// Token bucket: allows bursts of `capacity` events, refilled at `per_second`.
+pub struct RateLimiter {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, per_second: f64) -> RateLimiter {
+        RateLimiter {
+            capacity: capacity as f64,
+            per_second,
+            tokens: capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is available.
+    pub fn allow(&mut self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn bursts_are_limited_and_refilled() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(2, 1.0);
+
+        assert!(limiter.allow_at(start));
+        assert!(limiter.allow_at(start));
+        assert!(!limiter.allow_at(start));
+        assert!(limiter.allow_at(start + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::chat_log::fnv1a;
+use crate::file_writer::FileWriter;
+use shared::{LwwMap, RoomStateUpdate, MAX_STATE_KEY_LENGTH, MAX_STATE_VALUE_LENGTH};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// How far above the room's clock a client's writes may be stamped. Clients
+/// are a little ahead after writing offline or after a restart lost the
+/// room's clock, a lot ahead only to keep their writes from being overwritten.
+const MAX_CLOCK_LEAD: u64 = 1 << 20;
+
+/// A room's replicated key-value state, optionally backed by a file.
+///
+/// The server is one more replica: it merges what clients send, relays
+/// the entries that won and writes a snapshot of the map on every change.
+pub struct RoomState {
+    map: LwwMap,
+    key_limit: usize,
+    path: Option<PathBuf>,
+    writer: FileWriter,
+}
+
+impl RoomState {
+    /// Opens the state of room `key`, loading it from `dir` if given.
+    /// Changes are written out by `writer`.
+    pub fn open(dir: Option<&Path>, key: &str, key_limit: usize, writer: FileWriter) -> RoomState {
+        let path = dir.map(|dir| dir.join(format!("{:016x}.state", fnv1a(key.as_bytes()))));
+        let mut state = RoomState {
+            map: LwwMap::new(),
+            key_limit,
+            path,
+            writer,
+        };
+
+        if let Some(path) = &state.path {
+            match fs::read(path) {
+                Ok(bytes) => match RoomStateUpdate::deserialize(&bytes) {
+                    Ok(snapshot) => {
+                        state.map.merge(snapshot);
+                    }
+                    Err(e) => warn!(?path, error = %e, "failed to load room state"),
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!(?path, error = %e, "failed to load room state"),
+            }
+        }
+
+        state
+    }
+
+    pub fn snapshot(&self) -> RoomStateUpdate {
+        self.map.snapshot()
+    }
+
+    /// Merges entries from client `replica`, skipping oversized ones, ones
+    /// stamped too far ahead and new keys past the room's limit. Entries are
+    /// restamped as written by `replica`. Returns the entries that changed.
+    pub fn merge(&mut self, replica: u64, mut update: RoomStateUpdate) -> RoomStateUpdate {
+        let mut new_keys = 0;
+        let max_clock = self.map.clock().saturating_add(MAX_CLOCK_LEAD);
+        update.entries.retain_mut(|(key, register)| {
+            register.stamp.replica = replica;
+            if key.len() > MAX_STATE_KEY_LENGTH
+                || register.stamp.clock > max_clock
+                || register
+                    .value
+                    .as_ref()
+                    .is_some_and(|value| value.len() > MAX_STATE_VALUE_LENGTH)
+            {
+                return false;
+            }
+            if self.map.contains_key(key) {
+                return true;
+            }
+            new_keys += 1;
+            self.map.len() + new_keys <= self.key_limit
+        });
+
+        let changed = self.map.merge(update);
+        if !changed.entries.is_empty() {
+            self.persist();
+        }
+        changed
+    }
+
+    fn persist(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        match self.map.snapshot().serialize() {
+            Ok(bytes) => self.writer.replace(path, bytes, "room state"),
+            Err(e) => warn!(?path, error = %e, "failed to encode room state"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_apply_to_new_keys() {
+        let mut client = LwwMap::new();
+        let mut state = RoomState::open(None, "example.com", 2, FileWriter::default());
+
+        let mut update = client.set(1, "a", Some(String::from("1")));
+        update
+            .entries
+            .extend(client.set(1, "b", Some(String::from("2"))).entries);
+        update
+            .entries
+            .extend(client.set(1, "c", Some(String::from("3"))).entries);
+        let long = "x".repeat(MAX_STATE_VALUE_LENGTH + 1);
+        update
+            .entries
+            .extend(client.set(1, "a", Some(long)).entries);
+
+        assert_eq!(state.merge(1, update).entries.len(), 2);
+        assert_eq!(state.snapshot().entries.len(), 2);
+
+        // existing keys can still change
+        let changed = state.merge(1, client.set(1, "b", None));
+        assert_eq!(changed.entries.len(), 1);
+    }
+
+    #[test]
+    fn clients_cant_stamp_as_others_or_far_ahead() {
+        let mut state = RoomState::open(None, "example.com", 10, FileWriter::default());
+
+        let spoofed = LwwMap::new().set(9, "a", Some(String::from("1")));
+        let changed = state.merge(2, spoofed);
+        assert_eq!(changed.entries[0].1.stamp.replica, 2);
+
+        let mut ahead = LwwMap::new().set(2, "a", Some(String::from("2")));
+        ahead.entries[0].1.stamp.clock = u64::MAX;
+        assert!(state.merge(2, ahead).entries.is_empty());
+        assert_eq!(state.snapshot().entries[0].1.value.as_deref(), Some("1"));
+    }
+}
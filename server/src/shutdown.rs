@@ -0,0 +1,61 @@
+use crate::health::Health;
+use crate::metrics::Metrics;
+use actix_web::dev::ServerHandle;
+use actix_web::rt::{signal, time::sleep};
+use actix_web::web::Data;
+use futures_util::future::select;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How often the number of open connections is checked while draining.
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+/// Time given to close frames to go out before the server stops.
+const CLOSE_GRACE: Duration = Duration::from_millis(500);
+
+/// Waits for SIGTERM or ctrl-c, then shuts the server down gently: clients
+/// are asked to reconnect elsewhere, and whoever is still connected after
+/// `timeout` gets their socket closed before the server stops.
+pub async fn drain_on_signal(
+    server: ServerHandle,
+    health: Data<Health>,
+    metrics: Data<Metrics>,
+    timeout: Duration,
+) {
+    wait_for_signal().await;
+    info!(
+        connections = metrics.connections.get(),
+        "shutting down, draining connections"
+    );
+    health.drain();
+
+    let deadline = Instant::now() + timeout;
+    while metrics.connections.get() > 0 && Instant::now() < deadline {
+        sleep(DRAIN_POLL).await;
+    }
+
+    let remaining = metrics.connections.get();
+    if remaining > 0 {
+        info!(connections = remaining, "closing remaining connections");
+        health.close();
+        sleep(CLOSE_GRACE).await;
+    }
+    server.stop(true).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            select(Box::pin(terminate.recv()), Box::pin(signal::ctrl_c())).await;
+        }
+        Err(e) => {
+            warn!(error = %e, "failed to listen for SIGTERM");
+            let _ = signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = signal::ctrl_c().await;
+}
@@ -1,5 +1,15 @@
 use serde::{Deserialize, Serialize};
 
+mod page_policy;
+mod room_state;
+
+pub use page_policy::{
+    PagePolicy, RoomConfig, MIN_ROOM_SIZE, POLICY_META_NAME, POLICY_WELL_KNOWN_PATH,
+};
+pub use room_state::{
+    LwwMap, Register, RoomStateUpdate, Stamp, MAX_STATE_KEY_LENGTH, MAX_STATE_VALUE_LENGTH,
+};
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Message {
     pub message_type: MessageType,
@@ -23,6 +33,42 @@ pub enum MessageType {
     MyLocation,
     Spawn,
     Despawn,
+    MyViewport,
+    UserViewport,
+    Follow,
+    Presence,
+    MyChat,
+    UserChat,
+    MyChatMessage,
+    UserChatMessage,
+    ChatHistoryRequest,
+    ChatHistory,
+    MyPing,
+    UserPing,
+    MyReaction,
+    UserReaction,
+    MySelection,
+    UserSelection,
+    MyAnnotation,
+    UserAnnotation,
+    AnnotationSync,
+    MyStrokeStart,
+    UserStrokeStart,
+    MyStrokeAppend,
+    UserStrokeAppend,
+    MyStrokeEnd,
+    UserStrokeEnd,
+    MyStrokeErase,
+    UserStrokeErase,
+    StrokeSync,
+    MyStateUpdate,
+    UserStateUpdate,
+    GoingAway,
+    MyModeration,
+    UserModeration,
+    MyBlockList,
+    MyRoomConfig,
+    AuthAck,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -44,6 +90,9 @@ impl UserLocation {
 pub struct Auth {
     pub id: u64,
     pub url: String,
+    /// Member whose instance of the room we'd like to join, rooms that
+    /// fill up overflow into more instances.
+    pub invited_by: Option<u64>,
 }
 
 impl Auth {
@@ -56,11 +105,19 @@ impl Auth {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Location {
     pub id: u64,
     pub x: f32,
     pub y: f32,
+    /// Per-connection counter assigned by the sender, used to drop stale updates.
+    pub seq: u32,
+    /// Sender clock in milliseconds when the position was sampled.
+    pub timestamp: f64,
+    pub space: CoordinateSpace,
+    /// Element the cursor is over. Receivers that can find the same element
+    /// place the cursor relative to it and fall back to `x` and `y` otherwise.
+    pub anchor: Option<ElementAnchor>,
 }
 
 impl Location {
@@ -73,6 +130,29 @@ impl Location {
     }
 }
 
+/// What `x` and `y` of a position are relative to.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum CoordinateSpace {
+    /// Fractions of the sender's viewport, ignoring scroll.
+    Viewport,
+    /// Fractions of the sender's full document width and height, scroll included.
+    Document,
+}
+
+/// Longest element path accepted in an `ElementAnchor`.
+pub const MAX_ANCHOR_PATH: usize = 256;
+
+/// A position expressed relative to an element, so it survives layouts that
+/// differ between viewport widths.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ElementAnchor {
+    /// CSS selector that identifies the element, e.g. `#main > p:nth-of-type(2)`.
+    pub path: String,
+    /// Fractions of the element's bounding box.
+    pub x: f32,
+    pub y: f32,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Spawn {
     pub id: u64,
@@ -104,6 +184,613 @@ impl Despawn {
     }
 }
 
+/// The part of the page a user is looking at.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Viewport {
+    pub id: u64,
+    /// Scroll offset in CSS pixels.
+    pub scroll_x: f32,
+    pub scroll_y: f32,
+    /// Visible region as fractions of the document width and height.
+    pub left: f32,
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Viewport, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Sent by a client to start (`Some`) or stop (`None`) following a peer's viewport.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Follow {
+    pub target: Option<u64>,
+}
+
+impl Follow {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Follow, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// What a user is doing besides moving their cursor, broadcast to the room.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Presence {
+    pub id: u64,
+    /// The user whose viewport this user is following.
+    pub following: Option<u64>,
+}
+
+impl Presence {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Presence, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Longest chat bubble text, in characters.
+pub const MAX_CHAT_LENGTH: usize = 140;
+
+/// Short-lived text shown next to a user's cursor.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Chat {
+    pub id: u64,
+    pub text: String,
+    /// The user is still typing `text`. An empty, finished message clears the bubble.
+    pub typing: bool,
+}
+
+impl Chat {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Chat, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Cuts `text` down to `MAX_CHAT_LENGTH` characters.
+    pub fn truncate(&mut self) {
+        if let Some((index, _)) = self.text.char_indices().nth(MAX_CHAT_LENGTH) {
+            self.text.truncate(index);
+        }
+    }
+}
+
+/// Longest chat log message, in characters.
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 1000;
+
+/// Most chat log messages returned in one `ChatHistory` page.
+pub const MAX_CHAT_HISTORY_PAGE: u32 = 50;
+
+/// A message in the room's chat log.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ChatMessage {
+    /// Position in the room's log, assigned by the server.
+    pub seq: u64,
+    pub id: u64,
+    pub text: String,
+    /// Unix time in milliseconds, assigned by the server.
+    pub timestamp: u64,
+}
+
+impl ChatMessage {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<ChatMessage, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Cuts `text` down to `MAX_CHAT_MESSAGE_LENGTH` characters.
+    pub fn truncate(&mut self) {
+        if let Some((index, _)) = self.text.char_indices().nth(MAX_CHAT_MESSAGE_LENGTH) {
+            self.text.truncate(index);
+        }
+    }
+}
+
+/// Asks for chat log messages older than `before` (or the newest ones).
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct ChatHistoryRequest {
+    pub before: Option<u64>,
+    pub limit: u32,
+}
+
+impl ChatHistoryRequest {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<ChatHistoryRequest, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// A page of the chat log, oldest message first.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ChatHistory {
+    pub messages: Vec<ChatMessage>,
+    /// Older messages exist before the first one in `messages`.
+    pub has_more: bool,
+}
+
+impl ChatHistory {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<ChatHistory, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// A momentary effect at a spot on the page, shown to everyone in the room.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Ping {
+    pub id: u64,
+    pub x: f32,
+    pub y: f32,
+    pub space: CoordinateSpace,
+    pub kind: PingKind,
+}
+
+impl Ping {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Ping, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum PingKind {
+    /// "Here": a plain ripple.
+    Ripple,
+    /// "Look at this".
+    Look,
+    /// "Careful".
+    Warning,
+}
+
+impl PingKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PingKind::Ripple => "ripple",
+            PingKind::Look => "look",
+            PingKind::Warning => "warning",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PingKind> {
+        match name {
+            "ripple" => Some(PingKind::Ripple),
+            "look" => Some(PingKind::Look),
+            "warning" => Some(PingKind::Warning),
+            _ => None,
+        }
+    }
+}
+
+/// Longest reaction accepted, in bytes. Room for an emoji with modifiers.
+pub const MAX_REACTION_LENGTH: usize = 32;
+
+/// An emoji floating up from a spot on the page.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Reaction {
+    pub id: u64,
+    pub x: f32,
+    pub y: f32,
+    pub space: CoordinateSpace,
+    pub emoji: String,
+}
+
+impl Reaction {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Reaction, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Longest selected text that is shared, in characters.
+pub const MAX_SELECTION_LENGTH: usize = 2000;
+
+/// Characters of surrounding text kept on each side of a selection.
+pub const SELECTION_CONTEXT_LENGTH: usize = 32;
+
+/// A user's text selection, or `None` once it is cleared.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TextSelection {
+    pub id: u64,
+    pub quote: Option<TextQuote>,
+}
+
+impl TextSelection {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<TextSelection, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Selected text described without DOM node references: the element that
+/// contains it, the text itself and a little of what comes before and after,
+/// so it can be found again in a page with a slightly different DOM.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TextQuote {
+    /// Selector of the closest element containing the whole selection.
+    pub path: String,
+    pub exact: String,
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl TextQuote {
+    /// Whether every part is within the shared limits.
+    pub fn fits(&self) -> bool {
+        !self.exact.is_empty()
+            && self.path.len() <= MAX_ANCHOR_PATH
+            && self.exact.chars().count() <= MAX_SELECTION_LENGTH
+            && self.prefix.chars().count() <= SELECTION_CONTEXT_LENGTH
+            && self.suffix.chars().count() <= SELECTION_CONTEXT_LENGTH
+    }
+}
+
+/// Longest annotation text, in characters.
+pub const MAX_ANNOTATION_LENGTH: usize = 2000;
+
+/// A sticky note left on the page, kept by the server for later visitors.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Annotation {
+    /// Assigned by the server, `0` when asking to create a new one.
+    pub id: u64,
+    /// User who created it, the only one allowed to change it. The server
+    /// hands it to their new id when they reconnect from the same address.
+    pub author: u64,
+    pub x: f32,
+    pub y: f32,
+    pub space: CoordinateSpace,
+    pub anchor: Option<ElementAnchor>,
+    pub text: String,
+    /// Milliseconds since the Unix epoch, set by the server.
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl Annotation {
+    /// Cuts `text` down to `MAX_ANNOTATION_LENGTH` characters.
+    pub fn truncate(&mut self) {
+        if let Some((index, _)) = self.text.char_indices().nth(MAX_ANNOTATION_LENGTH) {
+            self.text.truncate(index);
+        }
+    }
+}
+
+/// Creates, updates or deletes an annotation. Sent by clients as
+/// `MyAnnotation` and relayed by the server as `UserAnnotation`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum AnnotationChange {
+    /// Creates the annotation when its `id` is `0`, otherwise replaces
+    /// its text and position.
+    Upsert(Annotation),
+    Delete(u64),
+}
+
+impl AnnotationChange {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<AnnotationChange, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Every annotation of the room, sent after joining.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AnnotationSync {
+    pub annotations: Vec<Annotation>,
+}
+
+impl AnnotationSync {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<AnnotationSync, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Most points kept for one stroke.
+pub const MAX_STROKE_POINTS: usize = 4000;
+
+/// A point of a stroke in document space, quantized to 16 bits per axis
+/// which is finer than a pixel on any realistic page and half the size of
+/// two `f32`s on the wire.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct StrokePoint {
+    pub x: u16,
+    pub y: u16,
+}
+
+impl StrokePoint {
+    /// Quantizes document fractions, clamping them to the document.
+    pub fn new(x: f32, y: f32) -> StrokePoint {
+        StrokePoint {
+            x: (x.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16,
+            y: (y.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16,
+        }
+    }
+
+    /// The point as document fractions.
+    pub fn position(&self) -> (f32, f32) {
+        (
+            self.x as f32 / u16::MAX as f32,
+            self.y as f32 / u16::MAX as f32,
+        )
+    }
+}
+
+/// Begins stroke `stroke` of user `id`. Stroke numbers are picked by the
+/// author and only need to be unique among their own strokes.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct StrokeStart {
+    pub id: u64,
+    pub stroke: u32,
+    /// `0xRRGGBB`.
+    pub color: u32,
+    /// Line width in CSS pixels.
+    pub width: f32,
+}
+
+impl StrokeStart {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<StrokeStart, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Points added to the end of a stroke that is being drawn.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StrokeAppend {
+    pub id: u64,
+    pub stroke: u32,
+    pub points: Vec<StrokePoint>,
+}
+
+impl StrokeAppend {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<StrokeAppend, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// The author lifted the pen, no more points follow.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct StrokeEnd {
+    pub id: u64,
+    pub stroke: u32,
+}
+
+impl StrokeEnd {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<StrokeEnd, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Removes strokes of user `id`: their latest one (undo) or all of them.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct StrokeErase {
+    pub id: u64,
+    pub all: bool,
+}
+
+impl StrokeErase {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<StrokeErase, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// A whole stroke, as sent when joining a room.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Stroke {
+    pub id: u64,
+    pub stroke: u32,
+    pub color: u32,
+    pub width: f32,
+    pub points: Vec<StrokePoint>,
+}
+
+/// Every stroke of the room, oldest first, sent after joining.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StrokeSync {
+    pub strokes: Vec<Stroke>,
+}
+
+impl StrokeSync {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<StrokeSync, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Sent before the server shuts down: the client should disconnect and
+/// connect again after `reconnect_in` ms, when another server will take it.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct GoingAway {
+    pub reconnect_in: u32,
+}
+
+impl GoingAway {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<GoingAway, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// What the owner of a room can do to another member.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum ModerationAction {
+    /// Makes the target the room's owner.
+    Owner,
+    /// Stops relaying the target's events.
+    Mute,
+    Unmute,
+    /// Disconnects the target.
+    Kick,
+    /// Disconnects the target and keeps them out for `seconds`.
+    Ban,
+}
+
+impl ModerationAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModerationAction::Owner => "owner",
+            ModerationAction::Mute => "mute",
+            ModerationAction::Unmute => "unmute",
+            ModerationAction::Kick => "kick",
+            ModerationAction::Ban => "ban",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ModerationAction> {
+        match name {
+            "owner" => Some(ModerationAction::Owner),
+            "mute" => Some(ModerationAction::Mute),
+            "unmute" => Some(ModerationAction::Unmute),
+            "kick" => Some(ModerationAction::Kick),
+            "ban" => Some(ModerationAction::Ban),
+            _ => None,
+        }
+    }
+}
+
+/// User `id` applying `action` to `target`. The server tells the room
+/// (the target included) once it's done, with `id` 0 for its own doing,
+/// like handing ownership on when the owner leaves.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Moderation {
+    pub id: u64,
+    pub target: u64,
+    pub action: ModerationAction,
+    /// How long a ban lasts.
+    pub seconds: u32,
+}
+
+impl Moderation {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Moderation, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Sent once the server placed us in a room: which instance of it, the
+/// first being `0`, and how many members it has, us included.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct AuthAck {
+    pub instance: u32,
+    pub members: u32,
+}
+
+impl AuthAck {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<AuthAck, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Most peers one user can block, extra ones are ignored.
+pub const MAX_BLOCKED: usize = 1000;
+
+/// Peers a user doesn't want to see. Shared with the server if they like,
+/// which then stops sending them those peers' cursors, chat and effects.
+/// The room's content (drawings, annotations, state) still reaches them.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct BlockList {
+    pub users: Vec<u64>,
+    /// Hides every peer, blocked or not.
+    pub all: bool,
+}
+
+impl BlockList {
+    pub fn hides(&self, id: u64) -> bool {
+        self.all || self.users.contains(&id)
+    }
+
+    /// Adds `id`, returning whether it wasn't blocked yet.
+    pub fn block(&mut self, id: u64) -> bool {
+        if self.users.contains(&id) || self.users.len() >= MAX_BLOCKED {
+            return false;
+        }
+        self.users.push(id);
+        true
+    }
+
+    /// Removes `id`, returning whether it was blocked.
+    pub fn unblock(&mut self, id: u64) -> bool {
+        let before = self.users.len();
+        self.users.retain(|user| *user != id);
+        self.users.len() != before
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<BlockList, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,6 +800,7 @@ mod tests {
         let auth = Auth {
             id: 123,
             url: String::from("https://google.com/"),
+            invited_by: Some(7),
         };
 
         let message = Message {
@@ -137,6 +825,10 @@ mod tests {
             id: 123,
             x: 69.0,
             y: 420.0,
+            seq: 1,
+            timestamp: 1000.0,
+            space: CoordinateSpace::Viewport,
+            anchor: None,
         };
 
         let message = Message {
@@ -162,6 +854,10 @@ mod tests {
                 id: 123,
                 x: 69.0,
                 y: 420.0,
+                seq: 1,
+                timestamp: 1000.0,
+                space: CoordinateSpace::Document,
+                anchor: None,
             },
         };
 
@@ -181,4 +877,310 @@ mod tests {
 
         assert_eq!(external_locations, decoded_external_locations);
     }
+
+    #[test]
+    fn anchored_location_deserialize() {
+        let location = Location {
+            id: 123,
+            x: 0.25,
+            y: 0.75,
+            seq: 7,
+            timestamp: 1000.0,
+            space: CoordinateSpace::Document,
+            anchor: Some(ElementAnchor {
+                path: String::from("#main > p:nth-of-type(2)"),
+                x: 0.5,
+                y: 0.1,
+            }),
+        };
+
+        let decoded_location = Location::deserialize(&location.serialize().unwrap()).unwrap();
+
+        assert_eq!(location, decoded_location);
+    }
+
+    #[test]
+    fn viewport_deserialize() {
+        let viewport = Viewport {
+            id: 123,
+            scroll_x: 0.0,
+            scroll_y: 1200.0,
+            left: 0.0,
+            top: 0.4,
+            width: 1.0,
+            height: 0.25,
+        };
+
+        let message = Message {
+            message_type: MessageType::UserViewport,
+            data: viewport.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_viewport = Viewport::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(viewport, decoded_viewport);
+    }
+
+    #[test]
+    fn presence_deserialize() {
+        let presence = Presence {
+            id: 123,
+            following: Some(456),
+        };
+
+        let message = Message {
+            message_type: MessageType::Presence,
+            data: presence.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_presence = Presence::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(presence, decoded_presence);
+    }
+
+    #[test]
+    fn chat_deserialize() {
+        let chat = Chat {
+            id: 123,
+            text: String::from("hello"),
+            typing: true,
+        };
+
+        let message = Message {
+            message_type: MessageType::UserChat,
+            data: chat.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_chat = Chat::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(chat, decoded_chat);
+    }
+
+    #[test]
+    fn chat_truncate() {
+        let mut chat = Chat {
+            id: 123,
+            text: "é".repeat(MAX_CHAT_LENGTH + 10),
+            typing: false,
+        };
+
+        chat.truncate();
+
+        assert_eq!(chat.text.chars().count(), MAX_CHAT_LENGTH);
+    }
+
+    #[test]
+    fn chat_history_deserialize() {
+        let history = ChatHistory {
+            messages: vec![ChatMessage {
+                seq: 1,
+                id: 123,
+                text: String::from("hello"),
+                timestamp: 1690000000000,
+            }],
+            has_more: true,
+        };
+
+        let message = Message {
+            message_type: MessageType::ChatHistory,
+            data: history.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_history = ChatHistory::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(history, decoded_history);
+    }
+
+    #[test]
+    fn ping_deserialize() {
+        let ping = Ping {
+            id: 123,
+            x: 0.5,
+            y: 0.25,
+            space: CoordinateSpace::Document,
+            kind: PingKind::Look,
+        };
+
+        let message = Message {
+            message_type: MessageType::UserPing,
+            data: ping.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_ping = Ping::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(ping, decoded_ping);
+        assert_eq!(PingKind::from_name(ping.kind.name()), Some(ping.kind));
+    }
+
+    #[test]
+    fn reaction_deserialize() {
+        let reaction = Reaction {
+            id: 123,
+            x: 0.5,
+            y: 0.25,
+            space: CoordinateSpace::Document,
+            emoji: String::from("🎉"),
+        };
+
+        let message = Message {
+            message_type: MessageType::UserReaction,
+            data: reaction.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_reaction = Reaction::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(reaction, decoded_reaction);
+    }
+
+    #[test]
+    fn selection_deserialize() {
+        let selection = TextSelection {
+            id: 123,
+            quote: Some(TextQuote {
+                path: String::from("#content > p:nth-of-type(2)"),
+                exact: String::from("quick brown fox"),
+                prefix: String::from("The "),
+                suffix: String::from(" jumps"),
+            }),
+        };
+
+        let message = Message {
+            message_type: MessageType::UserSelection,
+            data: selection.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_selection = TextSelection::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(selection, decoded_selection);
+        assert!(decoded_selection.quote.unwrap().fits());
+    }
+
+    #[test]
+    fn annotation_deserialize() {
+        let change = AnnotationChange::Upsert(Annotation {
+            id: 7,
+            author: 123,
+            x: 0.5,
+            y: 0.25,
+            space: CoordinateSpace::Document,
+            anchor: None,
+            text: String::from("remember to fix this"),
+            created_at: 1_700_000_000_000,
+            updated_at: 1_700_000_000_000,
+        });
+
+        let message = Message {
+            message_type: MessageType::UserAnnotation,
+            data: change.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_change = AnnotationChange::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(change, decoded_change);
+    }
+
+    #[test]
+    fn stroke_deserialize() {
+        let append = StrokeAppend {
+            id: 123,
+            stroke: 4,
+            points: vec![StrokePoint::new(0.0, 1.0), StrokePoint::new(0.25, 2.0)],
+        };
+
+        let message = Message {
+            message_type: MessageType::UserStrokeAppend,
+            data: append.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+
+        let decoded_append = StrokeAppend::deserialize(&decoded_message.data).unwrap();
+
+        assert_eq!(append, decoded_append);
+        assert_eq!(decoded_append.points[1].position().1, 1.0);
+        assert!((decoded_append.points[1].position().0 - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn moderation_deserialize() {
+        let moderation = Moderation {
+            id: 1,
+            target: 2,
+            action: ModerationAction::Ban,
+            seconds: 3600,
+        };
+
+        let message = Message {
+            message_type: MessageType::MyModeration,
+            data: moderation.serialize().unwrap(),
+        };
+
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+
+        assert_eq!(message, decoded_message);
+        assert_eq!(
+            moderation,
+            Moderation::deserialize(&decoded_message.data).unwrap()
+        );
+        assert_eq!(
+            ModerationAction::from_name(moderation.action.name()),
+            Some(ModerationAction::Ban)
+        );
+    }
+
+    #[test]
+    fn block_list_deserialize() {
+        let mut block_list = BlockList::default();
+        assert!(block_list.block(7));
+        assert!(!block_list.block(7));
+        assert!(block_list.hides(7));
+        assert!(!block_list.hides(8));
+
+        let message = Message {
+            message_type: MessageType::MyBlockList,
+            data: block_list.serialize().unwrap(),
+        };
+        let decoded_message = Message::deserialize(&message.serialize().unwrap()).unwrap();
+        let mut decoded = BlockList::deserialize(&decoded_message.data).unwrap();
+        assert_eq!(block_list, decoded);
+
+        assert!(decoded.unblock(7));
+        assert!(!decoded.unblock(7));
+        decoded.all = true;
+        assert!(decoded.hides(8));
+    }
 }
@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+/// Name of the `<meta>` tag a page declares its policy with.
+pub const POLICY_META_NAME: &str = "multiplayer-web";
+
+/// Where a site declares the policy of all its pages.
+pub const POLICY_WELL_KNOWN_PATH: &str = "/.well-known/multiplayer-web.json";
+
+/// Smallest room a policy can ask for.
+pub const MIN_ROOM_SIZE: u32 = 2;
+
+/// What a website's owner allows on their pages, declared in a well-known
+/// file for the whole site or a meta tag for one page. Rooms span the whole
+/// site, so only the well-known file can shape them with `max_room_size`
+/// and `relay`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct PagePolicy {
+    pub enabled: bool,
+    /// Path prefixes multiplayer runs under, all paths if empty.
+    pub paths: Vec<String>,
+    pub max_room_size: Option<u32>,
+    /// Server to connect to instead of ours, a `wss://` url.
+    pub relay: Option<String>,
+}
+
+impl Default for PagePolicy {
+    fn default() -> PagePolicy {
+        PagePolicy {
+            enabled: true,
+            paths: Vec::new(),
+            max_room_size: None,
+            relay: None,
+        }
+    }
+}
+
+impl PagePolicy {
+    /// Parses the well-known file, e.g.
+    /// `{"paths": ["/docs"], "maxRoomSize": 20, "relay": "wss://relay.example.com/ws/"}`.
+    pub fn from_json(text: &str) -> Result<PagePolicy, Box<dyn std::error::Error>> {
+        let policy: PagePolicy = serde_json::from_str(text)?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Parses the `content` of the meta tag: `off`, or `;` separated
+    /// directives like `paths=/docs /blog`.
+    pub fn from_meta(content: &str) -> Result<PagePolicy, Box<dyn std::error::Error>> {
+        let mut policy = PagePolicy::default();
+        for directive in content.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            let (key, value) = directive.split_once('=').unwrap_or((directive, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "off" | "none" => policy.enabled = false,
+                "on" => policy.enabled = true,
+                "paths" => policy.paths = value.split_whitespace().map(String::from).collect(),
+                "max-room-size" | "relay" => {
+                    return Err(format!(
+                        "{} applies to the whole site, set it in {}",
+                        key.trim(),
+                        POLICY_WELL_KNOWN_PATH
+                    )
+                    .into())
+                }
+                other => return Err(format!("unknown directive {:?}", other).into()),
+            }
+        }
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(path) = self.paths.iter().find(|path| !path.starts_with('/')) {
+            return Err(format!("path {:?} doesn't start with /", path).into());
+        }
+        if let Some(relay) = self
+            .relay
+            .as_ref()
+            .filter(|relay| !relay.starts_with("wss://"))
+        {
+            return Err(format!("relay {:?} isn't a wss:// url", relay).into());
+        }
+        Ok(())
+    }
+
+    /// Whether multiplayer may run on `path`. Prefixes match whole
+    /// segments, `/docs` covers `/docs/intro` but not `/docsearch`.
+    pub fn allows(&self, path: &str) -> bool {
+        self.enabled
+            && (self.paths.is_empty()
+                || self.paths.iter().any(|prefix| {
+                    path.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                        rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/')
+                    })
+                }))
+    }
+
+    /// What applies on `path` of a site with policy `site`, whose page
+    /// declared `page`. A page can narrow where we run, the room is the
+    /// site's.
+    pub fn resolve(site: &PagePolicy, page: &PagePolicy, path: &str) -> PagePolicy {
+        PagePolicy {
+            enabled: site.allows(path) && page.allows(path),
+            paths: Vec::new(),
+            max_room_size: site.max_room_size,
+            relay: site.relay.clone(),
+        }
+    }
+
+    pub fn room_config(&self) -> RoomConfig {
+        RoomConfig {
+            max_members: self.max_room_size.map(|size| size.max(MIN_ROOM_SIZE)),
+        }
+    }
+}
+
+/// Settings of a room taken from its site's policy. Clients send it after
+/// `Auth`, the server applies it when it comes from the room's owner.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct RoomConfig {
+    pub max_members: Option<u32>,
+}
+
+impl RoomConfig {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<RoomConfig, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_and_well_known_policies_parse() {
+        assert!(!PagePolicy::from_meta("off").unwrap().enabled);
+
+        let page = PagePolicy::from_meta("paths=/docs /blog/").unwrap();
+        assert!(page.allows("/docs"));
+        assert!(page.allows("/docs/intro"));
+        assert!(page.allows("/blog/post"));
+        assert!(!page.allows("/docsearch"));
+        assert!(!page.allows("/"));
+
+        // one page can't shape the site's room
+        assert!(PagePolicy::from_meta("max-room-size=1").is_err());
+        assert!(PagePolicy::from_meta("relay=wss://relay.example.com").is_err());
+        assert!(PagePolicy::from_meta("paths=docs").is_err());
+        assert!(PagePolicy::from_meta("colour=blue").is_err());
+
+        let site =
+            PagePolicy::from_json(r#"{"maxRoomSize": 1, "relay": "wss://relay.example.com/ws/"}"#)
+                .unwrap();
+        assert!(site.enabled);
+        assert!(PagePolicy::from_json(r#"{"enabled": "no"}"#).is_err());
+        assert!(PagePolicy::from_json(r#"{"relay": "ws://insecure.example.com"}"#).is_err());
+
+        let resolved = PagePolicy::resolve(&site, &page, "/docs/intro");
+        assert!(resolved.enabled);
+        assert_eq!(resolved.room_config().max_members, Some(MIN_ROOM_SIZE));
+        assert_eq!(
+            resolved.relay.as_deref(),
+            Some("wss://relay.example.com/ws/")
+        );
+        assert!(!PagePolicy::resolve(&site, &page, "/shop").enabled);
+    }
+}
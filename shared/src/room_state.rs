@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Longest key of the room state, in bytes.
+pub const MAX_STATE_KEY_LENGTH: usize = 128;
+
+/// Longest value of the room state, in bytes.
+pub const MAX_STATE_VALUE_LENGTH: usize = 4096;
+
+/// Orders writes to the same key: a Lamport clock, ties broken by the
+/// writing replica's id so every replica picks the same winner.
+#[derive(
+    Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default, Hash,
+)]
+pub struct Stamp {
+    pub clock: u64,
+    pub replica: u64,
+}
+
+/// The latest write to a key. A `None` value is a deletion, kept so it
+/// can win over older writes that arrive later.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Register {
+    pub value: Option<String>,
+    pub stamp: Stamp,
+}
+
+/// Entries of a room's state, either everything (after joining) or just
+/// what changed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct RoomStateUpdate {
+    pub entries: Vec<(String, Register)>,
+}
+
+impl RoomStateUpdate {
+    pub fn serialize(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<RoomStateUpdate, Box<dyn std::error::Error>> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// A last-writer-wins map. Merging the same updates in any order, any
+/// number of times, leaves every replica with the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct LwwMap {
+    entries: BTreeMap<String, Register>,
+    /// Highest clock seen, new writes go above it.
+    clock: u64,
+}
+
+impl LwwMap {
+    pub fn new() -> LwwMap {
+        LwwMap::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.get(key)?.value.as_deref()
+    }
+
+    /// Keys and values that are set, in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, register)| Some((key.as_str(), register.value.as_deref()?)))
+    }
+
+    /// Whether `key` was ever written, deletions included.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Number of keys ever written, deletions included.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Highest clock seen.
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Writes (or with `None`, deletes) `key` as `replica`, returning the
+    /// update to send to the others.
+    pub fn set(&mut self, replica: u64, key: &str, value: Option<String>) -> RoomStateUpdate {
+        self.clock = self.clock.saturating_add(1);
+        let register = Register {
+            value,
+            stamp: Stamp {
+                clock: self.clock,
+                replica,
+            },
+        };
+        self.entries.insert(String::from(key), register.clone());
+        RoomStateUpdate {
+            entries: vec![(String::from(key), register)],
+        }
+    }
+
+    /// Applies `update`, returning the entries that won over what we had.
+    pub fn merge(&mut self, update: RoomStateUpdate) -> RoomStateUpdate {
+        let mut changed = Vec::new();
+        for (key, register) in update.entries {
+            self.clock = self.clock.max(register.stamp.clock);
+            let newer = self
+                .entries
+                .get(&key)
+                .is_none_or(|current| register.stamp > current.stamp);
+            if newer {
+                self.entries.insert(key.clone(), register.clone());
+                changed.push((key, register));
+            }
+        }
+        RoomStateUpdate { entries: changed }
+    }
+
+    /// Every entry, deletions included, to bring another replica up to date.
+    pub fn snapshot(&self) -> RoomStateUpdate {
+        RoomStateUpdate {
+            entries: self
+                .entries
+                .iter()
+                .map(|(key, register)| (key.clone(), register.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replicas_converge_in_any_order() {
+        let mut a = LwwMap::new();
+        let mut b = LwwMap::new();
+        let first = a.set(1, "title", Some(String::from("\"a\"")));
+        let second = b.set(2, "title", Some(String::from("\"b\"")));
+        let deleted = a.set(1, "color", None);
+
+        let mut forward = LwwMap::new();
+        forward.merge(first.clone());
+        forward.merge(second.clone());
+        forward.merge(deleted.clone());
+
+        let mut backward = LwwMap::new();
+        backward.merge(deleted);
+        backward.merge(second.clone());
+        backward.merge(first);
+        backward.merge(second);
+
+        // same clock, the higher replica id wins
+        assert_eq!(forward.get("title"), Some("\"b\""));
+        assert_eq!(forward.snapshot(), backward.snapshot());
+        assert_eq!(forward.get("color"), None);
+        assert!(forward.contains_key("color"));
+    }
+
+    #[test]
+    fn writes_after_merging_win() {
+        let mut a = LwwMap::new();
+        let mut b = LwwMap::new();
+        for _ in 0..5 {
+            a.set(1, "count", Some(String::from("1")));
+        }
+
+        let changed = b.merge(a.snapshot());
+        assert_eq!(changed.entries.len(), 1);
+        assert!(b.merge(a.snapshot()).entries.is_empty());
+
+        let update = b.set(0, "count", Some(String::from("2")));
+        a.merge(update);
+        assert_eq!(a.get("count"), Some("2"));
+        assert_eq!(a.iter().collect::<Vec<_>>(), vec![("count", "2")]);
+    }
+}